@@ -0,0 +1,24 @@
+// Packs the opening book into the compact format embedded by src/book.rs
+#[allow(dead_code)]
+#[path = "src/packed_book.rs"]
+mod packed_book;
+
+use std::path::Path;
+
+fn main() {
+    let source = "opening_book/bookDeepDist.dat";
+    println!("cargo:rerun-if-changed={source}");
+    // Catches the book being added with an older timestamp than the last build
+    println!("cargo:rerun-if-changed=opening_book");
+    println!("cargo:rerun-if-changed=src/packed_book.rs");
+    // The book isn't checked in, so a fresh checkout builds with an empty one, which src/book.rs reads as no book
+    let packed = match std::fs::read(source) {
+        Ok(records) => packed_book::pack(&records),
+        Err(error) => {
+            println!("cargo:warning=building without an opening book, couldn't read {source}: {error}");
+            Vec::new()
+        }
+    };
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    std::fs::write(Path::new(&out_dir).join("bookDeepDist.c4bk"), packed).unwrap();
+}
@@ -0,0 +1,83 @@
+use crate::game::*;
+
+// Rows are numbered from 1 at the bottom, so bit rows 0, 2 and 4 are the odd rows
+pub const ODD_ROWS: u64 = 0x0015151515151515;
+pub const EVEN_ROWS: u64 = 0x002a2a2a2a2a2a2a;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Threats {
+    // Every empty square that would complete a four for this player
+    pub all: u64,
+    // Threats that can be played this turn
+    pub immediate: u64,
+    pub odd: u64,
+    pub even: u64,
+    // Empty squares directly beneath an opponent threat. Filling one lets the opponent win on top of it.
+    pub poisoned: u64,
+    // Threats on the rows that favour this player in zugzwang play: odd rows for player one, even rows for player two
+    pub zugzwang: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreatAnalysis {
+    pub player1: Threats,
+    pub player2: Threats,
+    // Squares that are a threat for both players
+    pub shared: u64,
+}
+
+pub fn threat_analysis(game: &Game) -> ThreatAnalysis {
+    let p1_slots = game.board_set & game.board_p1;
+    let p2_slots = game.board_set & !game.board_p1;
+    let p1_winning = get_winning_squares(p1_slots, game.board_set);
+    let p2_winning = get_winning_squares(p2_slots, game.board_set);
+    let board_playable = game.get_board_playable();
+
+    let threats = |winning: u64, opponent_winning: u64, zugzwang_rows: u64| Threats {
+        all: winning,
+        immediate: winning & board_playable,
+        odd: winning & ODD_ROWS,
+        even: winning & EVEN_ROWS,
+        poisoned: (opponent_winning >> 1) & !game.board_set & *BOARD_MASK,
+        zugzwang: winning & zugzwang_rows,
+    };
+
+    ThreatAnalysis {
+        player1: threats(p1_winning, p2_winning, ODD_ROWS),
+        player2: threats(p2_winning, p1_winning, EVEN_ROWS),
+        shared: p1_winning & p2_winning,
+    }
+}
+
+// Per square flags used when flattening an analysis for the UI
+pub const CELL_P1_THREAT: u8 = 1;
+pub const CELL_P1_IMMEDIATE: u8 = 2;
+pub const CELL_P1_POISONED: u8 = 4;
+pub const CELL_P2_THREAT: u8 = 8;
+pub const CELL_P2_IMMEDIATE: u8 = 16;
+pub const CELL_P2_POISONED: u8 = 32;
+
+impl ThreatAnalysis {
+    // Flattens the analysis into one byte of flags per square, indexed column * ROWS + row
+    pub fn cell_flags(&self) -> Vec<u8> {
+        let mut cells = vec![0; (COLS * ROWS) as usize];
+        for col_num in 0..COLS {
+            for row_num in 0..ROWS {
+                let flags = [
+                    (self.player1.all, CELL_P1_THREAT),
+                    (self.player1.immediate, CELL_P1_IMMEDIATE),
+                    (self.player1.poisoned, CELL_P1_POISONED),
+                    (self.player2.all, CELL_P2_THREAT),
+                    (self.player2.immediate, CELL_P2_IMMEDIATE),
+                    (self.player2.poisoned, CELL_P2_POISONED),
+                ];
+                for (board, flag) in flags {
+                    if get_bit(board, col_num, row_num) {
+                        cells[(col_num * ROWS + row_num) as usize] |= flag;
+                    }
+                }
+            }
+        }
+        cells
+    }
+}
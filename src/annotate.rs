@@ -0,0 +1,117 @@
+use crate::game::*;
+use crate::engine::*;
+use crate::book::*;
+use crate::record::GameRecord;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveQuality {
+    Best,
+    // Keeps the theoretical result but wins slower or loses faster
+    Inaccuracy,
+    // Changes the theoretical result
+    Blunder,
+}
+
+impl MoveQuality {
+    pub fn name(self) -> &'static str {
+        match self {
+            MoveQuality::Best => "best",
+            MoveQuality::Inaccuracy => "inaccuracy",
+            MoveQuality::Blunder => "blunder",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MoveAnnotation {
+    pub col_num: u8,
+    // Solver score before the move, the best the player to move could get
+    pub best_score: i8,
+    // Score after the move, from the point of view of the same player
+    pub score: i8,
+    // A column that keeps best_score, None when the played move did
+    pub best_move: Option<u8>,
+    pub quality: MoveQuality,
+}
+
+impl MoveAnnotation {
+    pub fn to_json(self) -> String {
+        let best_move = self.best_move.map_or("null".to_string(), |col_num| col_num.to_string());
+        format!("{{\"column\":{},\"best_score\":{},\"score\":{},\"best_move\":{},\"quality\":\"{}\"}}",
+            self.col_num, self.best_score, self.score, best_move, self.quality.name())
+    }
+}
+
+// Score of the position for the player to move, or for the player who just moved once the game is over
+fn score_after_move(game: &mut Game, transposition_table: &mut TranspositionTable, book: &OpeningBook, nodes: &mut u64) -> i8 {
+    match game.game_status {
+        GameStatus::InProgress => -search(game, transposition_table, book, nodes),
+        GameStatus::Draw => 0,
+        // The player who just moved won
+        _ => 22 - (game.moves_made + 1)/2,
+    }
+}
+
+// Replays 0-6 columns through Game and grades every move from index `first` on against the solver, e.g. skipping
+// opening moves that would need a deep search without the book. One transposition table serves the whole game,
+// so consecutive positions reuse each other's results.
+pub fn annotate_moves(moves: &[u8], first: usize, transposition_table: &mut TranspositionTable, book: &OpeningBook,
+        nodes: &mut u64) -> Result<Vec<MoveAnnotation>, String> {
+    let mut game = Game::new();
+    let mut annotations = Vec::new();
+    for (i, &col_num) in moves.iter().enumerate() {
+        if game.game_status != GameStatus::InProgress {
+            return Err(format!("move {} is played after the game ended", i + 1));
+        }
+        if col_num >= COLS {
+            return Err(format!("move {}: no column {col_num}", i + 1));
+        }
+        let best_score = if i >= first {search(&mut game, transposition_table, book, nodes)} else {0};
+        let (true, row_number) = game.make_move(col_num) else {
+            return Err(format!("move {}: column {col_num} is full", i + 1));
+        };
+        if i < first {
+            continue;
+        }
+        let score = score_after_move(&mut game, transposition_table, book, nodes);
+
+        let quality = if score == best_score {
+            MoveQuality::Best
+        } else if score.signum() == best_score.signum() {
+            MoveQuality::Inaccuracy
+        } else {
+            MoveQuality::Blunder
+        };
+        let best_move = if quality == MoveQuality::Best {
+            None
+        } else {
+            game.unmake_move(col_num, row_number);
+            let best_move = (0..COLS).find(|&other| {
+                let (true, other_row) = game.make_move(other) else { return false };
+                let other_score = score_after_move(&mut game, transposition_table, book, nodes);
+                game.unmake_move(other, other_row);
+                other_score == best_score
+            });
+            game.make_move(col_num);
+            best_move
+        };
+        annotations.push(MoveAnnotation {col_num, best_score, score, best_move, quality});
+    }
+    Ok(annotations)
+}
+
+// Annotates a record's moves from `first` on and writes the scores into it. Moves that weren't best get a comment
+// saying so, replacing any comment they had.
+pub fn annotate_record(record: &mut GameRecord, first: usize, transposition_table: &mut TranspositionTable,
+        book: &OpeningBook, nodes: &mut u64) -> Result<Vec<MoveAnnotation>, String> {
+    record.replay()?;
+    let moves: Vec<u8> = record.moves.iter().map(|recorded| recorded.col_num).collect();
+    let annotations = annotate_moves(&moves, first, transposition_table, book, nodes)?;
+    for (recorded, annotation) in record.moves.iter_mut().skip(first).zip(&annotations) {
+        recorded.score = Some(annotation.score);
+        if let Some(best_move) = annotation.best_move {
+            recorded.comment = Some(format!("{}, {} scores {}", annotation.quality.name(), best_move + 1, annotation.best_score));
+        }
+    }
+    Ok(annotations)
+}
@@ -0,0 +1,239 @@
+use crate::game::*;
+use crate::engine::*;
+use crate::book::*;
+use crate::ordering::MoveOrdering;
+use crate::random::Rng;
+use crate::{read_test_file, setup_game};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::Instant;
+
+// Pascal Pons' standard test sets. L is how far into the game the position is (3 end, 2 middle, 1 beginning)
+// and R how many moves remain with perfect play (1 fewer than 14, 2 fewer than 28, 3 the rest).
+pub const TEST_SETS: [&str; 6] = ["Test_L3_R1", "Test_L2_R1", "Test_L2_R2", "Test_L1_R1", "Test_L1_R2", "Test_L1_R3"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetResult {
+    pub name: String,
+    pub positions: u64,
+    pub failed: u64,
+    pub mean_micros: u64,
+    pub median_micros: u64,
+    pub p99_micros: u64,
+    pub mean_nodes: u64,
+    pub median_nodes: u64,
+    pub p99_nodes: u64,
+}
+
+impl SetResult {
+    pub fn to_json(&self) -> String {
+        format!("{{\"name\":\"{}\",\"positions\":{},\"failed\":{},\"mean_micros\":{},\"median_micros\":{},\"p99_micros\":{},\"mean_nodes\":{},\"median_nodes\":{},\"p99_nodes\":{}}}",
+            self.name, self.positions, self.failed, self.mean_micros, self.median_micros, self.p99_micros,
+            self.mean_nodes, self.median_nodes, self.p99_nodes)
+    }
+
+    // Reads back an object written by to_json. Only the flat format written here is supported.
+    pub fn from_json(object: &str) -> Option<Self> {
+        let field = |key: &str| -> Option<&str> {
+            let start = object.find(&format!("\"{key}\":"))? + key.len() + 3;
+            let rest = &object[start..];
+            let end = rest.find([',', '}']).unwrap_or(rest.len());
+            Some(rest[..end].trim())
+        };
+        let number = |key: &str| field(key)?.parse().ok();
+        Some(Self {
+            name: field("name")?.trim_matches('"').to_string(),
+            positions: number("positions")?,
+            failed: number("failed")?,
+            mean_micros: number("mean_micros")?,
+            median_micros: number("median_micros")?,
+            p99_micros: number("p99_micros")?,
+            mean_nodes: number("mean_nodes")?,
+            median_nodes: number("median_nodes")?,
+            p99_nodes: number("p99_nodes")?,
+        })
+    }
+}
+
+// Nearest rank percentile of an already sorted slice
+fn percentile(sorted: &[u64], percent: u64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (sorted.len() as u64 * percent).div_ceil(100).max(1);
+    sorted[rank as usize - 1]
+}
+
+// Solves the first `limit` positions of a test file with a fresh transposition table so node counts are reproducible.
+// Fails on an unreadable file or a line with an illegal move sequence.
+pub fn run_set(name: &str, path: &Path, limit: usize, tt_bits: usize, book: &OpeningBook,
+        ordering: MoveOrdering, endgame_empty: i8) -> Result<SetResult, String> {
+    let (test_moves, test_evals) = read_test_file(&path.to_string_lossy())?;
+    let mut table = TranspositionTable::new(tt_bits);
+    let mut micros = Vec::new();
+    let mut nodes = Vec::new();
+    let mut failed = 0;
+
+    for (moves, expected) in test_moves.iter().zip(test_evals).take(limit) {
+        let mut game = Game::new();
+        setup_game(&mut game, moves).map_err(|message| format!("{}: {message}", path.display()))?;
+        let mut position_nodes = 0;
+        let start = Instant::now();
+        let eval = search_ordered(&mut game, &mut table, book, ordering, endgame_empty, &mut position_nodes);
+        micros.push(start.elapsed().as_micros() as u64);
+        nodes.push(position_nodes);
+        if eval != expected {
+            let moves: String = moves.iter().map(|col_num| (col_num + 1).to_string()).collect();
+            eprintln!("{name}: {moves} eval {eval}, answer {expected}");
+            failed += 1;
+        }
+    }
+
+    let positions = micros.len() as u64;
+    let mean_micros = micros.iter().sum::<u64>() / positions.max(1);
+    let mean_nodes = nodes.iter().sum::<u64>() / positions.max(1);
+    micros.sort_unstable();
+    nodes.sort_unstable();
+    Ok(SetResult {
+        name: name.to_string(),
+        positions,
+        failed,
+        mean_micros,
+        median_micros: percentile(&micros, 50),
+        p99_micros: percentile(&micros, 99),
+        mean_nodes,
+        median_nodes: percentile(&nodes, 50),
+        p99_nodes: percentile(&nodes, 99),
+    })
+}
+
+// Runs every standard set found in `dir`. Missing sets are skipped with a warning.
+pub fn run_suite(dir: &Path, limit: usize, tt_bits: usize, book: &OpeningBook, ordering: MoveOrdering, endgame_empty: i8)
+        -> Result<Vec<SetResult>, String> {
+    let mut results = Vec::new();
+    for name in TEST_SETS {
+        let path = dir.join(name);
+        if path.is_file() {
+            results.push(run_set(name, &path, limit, tt_bits, book, ordering, endgame_empty)?);
+        } else {
+            eprintln!("skipping {name}: {} not found", path.display());
+        }
+    }
+    Ok(results)
+}
+
+pub fn suite_json(results: &[SetResult]) -> String {
+    let sets: Vec<String> = results.iter().map(|result| result.to_json()).collect();
+    format!("{{\"sets\":[\n{}\n]}}\n", sets.join(",\n"))
+}
+
+pub fn read_baseline(path: &Path) -> Result<Vec<SetResult>, String> {
+    let contents = fs::read_to_string(path).map_err(|error| format!("couldn't read {}: {error}", path.display()))?;
+    contents.split('{')
+        .filter(|object| object.contains("\"name\""))
+        .map(|object| SetResult::from_json(object).ok_or_else(|| format!("malformed baseline entry {{{object}")))
+        .collect()
+}
+
+// Returns a description of every set whose mean node count grew by more than `threshold` percent,
+// or which now gets positions wrong
+pub fn compare(current: &[SetResult], baseline: &[SetResult], threshold: f64) -> Vec<String> {
+    let mut regressions = Vec::new();
+    for result in current {
+        if result.failed > 0 {
+            regressions.push(format!("{}: {} positions solved incorrectly", result.name, result.failed));
+        }
+        let Some(base) = baseline.iter().find(|base| base.name == result.name) else {
+            continue;
+        };
+        if base.positions != result.positions {
+            eprintln!("{}: baseline has {} positions, run has {}; node counts aren't comparable",
+                result.name, base.positions, result.positions);
+            continue;
+        }
+        let allowed = base.mean_nodes as f64 * (1.0 + threshold / 100.0);
+        if result.mean_nodes as f64 > allowed {
+            regressions.push(format!("{}: mean nodes {} up from {} ({:+.1}%)", result.name, result.mean_nodes,
+                base.mean_nodes, (result.mean_nodes as f64 / base.mean_nodes.max(1) as f64 - 1.0) * 100.0));
+        }
+    }
+    regressions
+}
+
+// Number of moves left until the game ends if both sides play perfectly
+pub fn remaining_moves(moves_made: i8, score: i8) -> i8 {
+    if score == 0 {
+        return 42 - moves_made;
+    }
+    // The winner wins with their (22 - |score|)th stone
+    let winner_stones = 22 - score.abs();
+    let player_one_wins = (moves_made % 2 == 0) == (score > 0);
+    let end = if player_one_wins {2 * winner_stones - 1} else {2 * winner_stones};
+    end - moves_made
+}
+
+pub fn classify(moves_made: i8, score: i8) -> Option<&'static str> {
+    let level = match moves_made {
+        0..=14 => 1,
+        15..=28 => 2,
+        _ => 3,
+    };
+    let rate = match remaining_moves(moves_made, score) {
+        0..=13 => 1,
+        14..=27 => 2,
+        _ => 3,
+    };
+    let name = format!("Test_L{level}_R{rate}");
+    TEST_SETS.into_iter().find(|set| *set == name)
+}
+
+// Writes `count` random positions for every standard set into `dir`, scored by the solver.
+// Beginning positions are drawn from plies 8 to 14 so they solve quickly without the opening book.
+pub fn generate_fixtures(dir: &Path, count: usize, seed: u64, tt_bits: usize, book: &OpeningBook) -> Result<(), String> {
+    let mut table = TranspositionTable::new(tt_bits);
+    let mut rng = Rng::new(seed);
+    let mut sets: Vec<Vec<String>> = vec![Vec::new(); TEST_SETS.len()];
+    let ply_ranges: [(i8, i8); 3] = [(8, 14), (15, 28), (29, 41)];
+
+    while sets.iter().any(|set| set.len() < count) {
+        // Only draw from levels that still have an unfilled set
+        let open_levels: Vec<usize> = (1..=3)
+            .filter(|level| TEST_SETS.iter().zip(&sets)
+                .any(|(name, set)| set.len() < count && name.starts_with(&format!("Test_L{level}"))))
+            .collect();
+        let (min_ply, max_ply) = ply_ranges[open_levels[rng.below(open_levels.len() as u64) as usize] - 1];
+        let plies = min_ply + rng.below((max_ply - min_ply + 1) as u64) as i8;
+
+        let mut game = Game::new();
+        let mut moves = String::new();
+        while game.moves_made < plies && game.game_status == GameStatus::InProgress {
+            let col_num = rng.below(COLS as u64) as u8;
+            if let (true, _) = game.make_move(col_num) {
+                moves.push_str(&(col_num + 1).to_string());
+            }
+        }
+        if game.game_status != GameStatus::InProgress || game.get_winning_move().is_some() {
+            continue;
+        }
+
+        let mut nodes = 0;
+        let score = search(&mut game, &mut table, book, &mut nodes);
+        if let Some(name) = classify(game.moves_made, score) {
+            let set = &mut sets[TEST_SETS.iter().position(|set| *set == name).unwrap()];
+            if set.len() < count && !set.iter().any(|line| line.starts_with(&format!("{moves} "))) {
+                set.push(format!("{moves} {score}"));
+            }
+        }
+    }
+
+    fs::create_dir_all(dir).map_err(|error| format!("couldn't create {}: {error}", dir.display()))?;
+    for (name, set) in TEST_SETS.iter().zip(sets) {
+        let path = dir.join(name);
+        let mut file = fs::File::create(&path).map_err(|error| format!("couldn't create {}: {error}", path.display()))?;
+        for line in set {
+            writeln!(file, "{line}").map_err(|error| format!("couldn't write {}: {error}", path.display()))?;
+        }
+    }
+    Ok(())
+}
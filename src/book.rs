@@ -1,73 +1,361 @@
 use std::cmp::Ordering;
-use crate::game::set_bit;
-pub const BOOK_ENTRIES: usize = 4200899;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use crate::game::{set_bit, mirror_board, get_position_key, get_playable_squares, check_board_for_win, COLUMN_MASK, COLS};
+use crate::packed_book::{pack, PackedBook};
+use crate::tablebase::Tablebase;
+// Ply of every position in bookDeepDist.dat
+pub const BOOK_PLY: i8 = 12;
 pub struct OpeningBook{
-    pub positions : Box<[i32]>,
-    pub evals: Box<[i8]>,
+    // Huffman codes and bookDeepDist distance bytes, looked up in place without unpacking
+    packed: PackedBook<BookBytes>,
+    // Books for other plies, e.g. a deeper one built with `book build`
+    pub ply_books: Vec<PlyBook>,
+    // Endgame scores searched alongside the book. Empty unless one is attached with with_tablebase.
+    pub tablebase: Tablebase,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookHit {
+    pub eval: i8,
+    // Ply of the book the position was found in
+    pub ply: i8,
+}
+
+// Everything the ply 12 book holds about a position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookEntry {
+    pub code: i32,
+    // Whether the position matched through the huffman code of its mirror image
+    pub mirrored: bool,
+    // bookDeepDist distance byte as stored
+    pub raw: i8,
+    // Score for the side to move, as lookup returns it
+    pub eval: i8,
+    // Whether eval is one of the corrections rather than converted from raw
+    pub corrected: bool,
+    // Position of the entry in code order, as listed by entries
+    pub index: usize,
+}
+
+impl BookEntry {
+    pub fn to_json(self) -> String {
+        format!("{{\"code\":{},\"mirrored\":{},\"raw\":{},\"eval\":{},\"corrected\":{},\"index\":{}}}",
+            self.code, self.mirrored, self.raw, self.eval, self.corrected, self.index)
+    }
+}
+
+// How much of the ply 12 book lies below a position. Each count is of distinct positions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BookCoverage {
+    // Positions at BOOK_PLY reachable without the game ending
+    pub descendants: u64,
+    // Descendants found in the book
+    pub covered: u64,
+    // Positions up to BOOK_PLY where the game has just ended, which the book doesn't need
+    pub finished: u64,
+}
+
+impl BookCoverage {
+    pub fn is_complete(&self) -> bool {
+        self.covered == self.descendants
+    }
+
+    pub fn to_json(self) -> String {
+        format!("{{\"descendants\":{},\"covered\":{},\"finished\":{},\"complete\":{}}}",
+            self.descendants, self.covered, self.finished, self.is_complete())
+    }
+}
+
+// Where a packed book lives. Loading never copies it.
+pub enum BookBytes {
+    Static(&'static [u8]),
+    Owned(Box<[u8]>),
+    #[cfg(not(target_arch = "wasm32"))]
+    Mapped(memmap2::Mmap),
+}
+
+impl AsRef<[u8]> for BookBytes {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            BookBytes::Static(bytes) => bytes,
+            BookBytes::Owned(bytes) => bytes,
+            #[cfg(not(target_arch = "wasm32"))]
+            BookBytes::Mapped(map) => map,
+        }
+    }
+}
+
+// build.rs packs opening_book/bookDeepDist.dat into the packed_book format, about half its size, and embeds
+// an empty book when the file is missing. Tests run without the book so they behave the same either way.
+#[cfg(not(test))]
+static PACKED_BOOK: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/bookDeepDist.c4bk"));
+#[cfg(test)]
+static PACKED_BOOK: &[u8] = &[];
+
+// Correct opening book issues found by ddrhoardarmer
+// https://github.com/MarkusThill/Connect-Four/issues/3
+pub fn book_correction(code: i32) -> Option<i8> {
+    match code {
+        -689592004 => Some(7),
+        2101158888 => Some(4),
+        1599634104 => Some(2),
+        _ => None,
+    }
+}
+
+// Score for the side to move from a bookDeepDist distance byte. Applied on each lookup so the book is never remapped.
+pub fn book_eval(code: i32, raw: i8) -> i8 {
+    if let Some(eval) = book_correction(code) {
+        return eval;
+    }
+    if raw > 0 {
+        15 - (100 - raw)/2
+    } else if raw < 0 {
+        - 15 - (-99-raw)/2
+    } else {
+        0
+    }
 }
 
 impl OpeningBook {
+    // The book embedded by build.rs, read in place from the binary or the wasm module's static data
     pub fn new() -> Self{
-        let bytes = include_bytes!("../opening_book/bookDeepDist.dat");
-        let positions: Vec<i32> = bytes.chunks_exact(5)
-        .map(|bytes| i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
-        .collect();
-        let positions: Box<[i32; 4200899]> = positions.into_boxed_slice()
-            .try_into().expect("could not read book");
-        let evals: Vec<i8> = bytes.chunks_exact(5)
-            .map(|bytes| {
-            let bookeval = bytes[4] as i8; // Convert to i16 to prevent immediate overflow
-            if bookeval > 0 {
-                15 - (100 - bookeval)/2
-            } else if bookeval < 0 {
-                - 15 - (-99-bookeval)/2
-            } else {
-                0
-            }
-            })
-            .collect();
+        Self::from_packed(BookBytes::Static(PACKED_BOOK)).expect("build.rs writes a valid packed book")
+    }
 
-        let mut evals: Box<[i8; 4200899]> = evals.into_boxed_slice()
-        .try_into().expect("could not read book");
-        for i in 0..BOOK_ENTRIES{
-            // Correct opening book issues found by ddrhoardarmer
-            // https://github.com/MarkusThill/Connect-Four/issues/3
-            match positions[i] {
-                -689592004 => {evals[i]=7}
-                2101158888 => {evals[i]=4}
-                1599634104 => {evals[i]=2}
-                _ => {},
-            }
-        }
-        OpeningBook {positions, evals}
+    // Reads 5 byte records of a big endian huffman code followed by a bookDeepDist distance byte
+    pub fn from_bytes(bytes: &[u8]) -> Self{
+        Self::from_packed(BookBytes::Owned(pack(bytes).into_boxed_slice())).expect("pack writes a valid packed book")
+    }
+
+    // Memory maps a packed book file, e.g. one written by `book pack`. Pages are read as lookups touch them.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open(path: &Path) -> Result<Self, String>{
+        let file = fs::File::open(path).map_err(|error| format!("couldn't open {}: {error}", path.display()))?;
+        // SAFETY: the map is only read, and book files aren't expected to change while the engine runs
+        let map = unsafe {memmap2::Mmap::map(&file)}.map_err(|error| format!("couldn't map {}: {error}", path.display()))?;
+        Self::from_packed(BookBytes::Mapped(map)).map_err(|error| format!("{}: {error}", path.display()))
+    }
+
+    pub fn from_packed(bytes: BookBytes) -> Result<Self, String>{
+        Ok(OpeningBook {packed: PackedBook::parse(bytes)?, ply_books: Vec::new(), tablebase: Tablebase::empty()})
+    }
+
+    pub fn len(&self) -> usize{
+        self.packed.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.packed.is_empty()
+    }
+
+    pub fn packed_size(&self) -> usize{
+        self.packed.size()
+    }
+
+    // Every (huffman code, score) in code order
+    pub fn entries(&self) -> impl Iterator<Item = (i32, i8)> + '_{
+        self.packed.entries().map(|(code, raw)| (code, book_eval(code, raw)))
+    }
+
+    // The (huffman code, score) at `index` of entries, without walking the ones before it
+    pub fn entry(&self, index: usize) -> Option<(i32, i8)>{
+        self.packed.entry(index).map(|(code, raw)| (code, book_eval(code, raw)))
+    }
+
+    pub fn with_tablebase(mut self, tablebase: Tablebase) -> Self{
+        self.tablebase = tablebase;
+        self
+    }
+
+    // Adds a book for another ply, replacing any already held for that ply
+    pub fn with_ply_book(mut self, ply_book: PlyBook) -> Self{
+        self.ply_books.retain(|held| held.ply != ply_book.ply);
+        self.ply_books.push(ply_book);
+        self
+    }
+
+    pub fn covers(&self, ply: i8) -> bool{
+        (ply == BOOK_PLY && !self.is_empty()) || self.ply_books.iter().any(|ply_book| ply_book.ply == ply && !ply_book.is_empty())
+    }
+
+    // Looks the position up in whichever book holds its ply. Scores are for the side to move.
+    pub fn lookup_position(&self, board_set: u64, board_p1: u64) -> Option<BookHit>{
+        let ply = board_set.count_ones() as i8;
+        let eval = if ply == BOOK_PLY {
+            self.lookup(board_set, board_p1)
+        } else {
+            None
+        };
+        eval.or_else(|| {
+            let ply_book = self.ply_books.iter().find(|ply_book| ply_book.ply == ply)?;
+            let player_squares = if ply % 2 == 0 {board_set & board_p1} else {board_set & !board_p1};
+            ply_book.lookup(player_squares, board_set)
+        })
+        .map(|eval| BookHit {eval, ply})
     }
 
     pub fn lookup(&self, board_set:u64, board_p1: u64)->Option<i8> {
-        let code = huffman_code(board_set, board_p1, false);
-        let eval = self.search(code, 0, BOOK_ENTRIES-1);
-        if eval != None{
-            return eval;
+        self.lookup_entry(board_set, board_p1).map(|entry| entry.eval)
+    }
+
+    // Like lookup, but says which orientation matched and what the book stores
+    pub fn lookup_entry(&self, board_set:u64, board_p1: u64)->Option<BookEntry> {
+        if self.is_empty() {
+            return None;
         }
+        self.find_entry(huffman_code(board_set, board_p1, false), false)
+            .or_else(|| self.find_entry(huffman_code(board_set, board_p1, true), true))
+    }
 
-        let code_reverse = huffman_code(board_set, board_p1, true);
-        let eval = self.search(code_reverse, 0, BOOK_ENTRIES-1);
-        return eval;
+    fn find_entry(&self, code: i32, mirrored: bool)->Option<BookEntry>{
+        let (index, raw) = self.packed.find_entry(code)?;
+        Some(BookEntry {code, mirrored, raw, eval: book_eval(code, raw), corrected: book_correction(code).is_some(), index})
     }
 
-    pub fn search(&self, pos:i32, start:usize, end:usize)->Option<i8>{
-        if start > end {
-            None
-        } else {
-            let mid = (start + end) / 2;
-            match pos.cmp(&self.positions[mid]){
-                Ordering::Less => {self.search(pos, start, mid-1)},
-                Ordering::Equal => {Some(self.evals[mid])},
-                Ordering::Greater => {self.search(pos, mid+1, end)},
+    pub fn find(&self, code: i32)->Option<i8>{
+        self.packed.find(code).map(|raw| book_eval(code, raw))
+    }
+
+    // Walks every continuation of a position at or before BOOK_PLY down to BOOK_PLY and counts how many the
+    // book holds. None past BOOK_PLY. From the empty board that's most of the 12,236,101 positions at ply 12,
+    // so it takes a while. Mirror images are walked once, keyed by their smaller huffman code.
+    pub fn coverage(&self, board_set: u64, board_p1: u64)->Option<BookCoverage>{
+        let ply = board_set.count_ones() as i8;
+        if ply > BOOK_PLY {
+            return None;
+        }
+        let mut coverage = BookCoverage::default();
+        let p1_squares = board_set & board_p1;
+        let just_moved = if ply % 2 == 0 {board_set & !p1_squares} else {p1_squares};
+        if check_board_for_win(just_moved) {
+            coverage.finished = 1;
+            return Some(coverage);
+        }
+        // Positions at the current ply, with mirror_class bits for which of each pair were reached
+        let (code, reached, _) = mirror_class(board_set, p1_squares);
+        let mut frontier: HashMap<i32, u8> = HashMap::from([(code, reached)]);
+        for ply in ply..BOOK_PLY {
+            let mut next = HashMap::new();
+            let mut finished = HashMap::new();
+            for (&code, &reached) in &frontier {
+                // decode expects a full length code
+                let (played, p1_squares) = decode(code << (2 * (BOOK_PLY - ply)));
+                let playable = get_playable_squares(played);
+                for col_num in 0..COLS {
+                    let square = playable & (COLUMN_MASK << (8 * col_num));
+                    if square == 0 {
+                        continue;
+                    }
+                    let child = (played | square, if ply % 2 == 0 {p1_squares | square} else {p1_squares});
+                    let mover = if ply % 2 == 0 {child.1} else {child.0 & !child.1};
+                    let (child_code, own, mirror) = mirror_class(child.0, child.1);
+                    // The same move from the mirror image reaches the mirrored child
+                    let child_reached = if reached & 1 != 0 {own} else {0} | if reached & 2 != 0 {mirror} else {0};
+                    let layer = if check_board_for_win(mover) {&mut finished} else {&mut next};
+                    *layer.entry(child_code).or_insert(0) |= child_reached;
+                }
             }
+            coverage.finished += finished.values().map(|reached| reached.count_ones() as u64).sum::<u64>();
+            frontier = next;
         }
+        for (&code, &reached) in &frontier {
+            let (played, p1_squares) = decode(code);
+            coverage.descendants += reached.count_ones() as u64;
+            if self.lookup(played, p1_squares).is_some() {
+                coverage.covered += reached.count_ones() as u64;
+            }
+        }
+        Some(coverage)
     }
  }
 
+// The smaller of a position's two huffman codes, then the bit that marks the position itself as reached and
+// the bit that marks its mirror image: 1 for the one with the smaller code and 2 for the other. Both are 1 when
+// the position is symmetric, as it is its own mirror image.
+fn mirror_class(board_set: u64, board_p1: u64) -> (i32, u8, u8) {
+    let code = huffman_code(board_set, board_p1, false);
+    let mirrored = huffman_code(board_set, board_p1, true);
+    match code.cmp(&mirrored) {
+        Ordering::Less => (code, 1, 2),
+        Ordering::Equal => (code, 1, 1),
+        Ordering::Greater => (mirrored, 2, 1),
+    }
+}
+
+// Scores for positions at a single ply, keyed by get_position_key. A position and its mirror image share one
+// entry under the smaller of their two keys. The file is the magic bytes, the ply, then one little endian
+// u64 per position sorted ascending with the key above a low byte holding the score for the side to move.
+pub struct PlyBook {
+    pub ply: i8,
+    records: Box<[u64]>,
+}
+
+const PLY_BOOK_MAGIC: &[u8; 4] = b"C4PB";
+
+fn canonical_key(player_squares: u64, played: u64) -> u64 {
+    get_position_key(player_squares, played).min(get_position_key(mirror_board(player_squares), mirror_board(played)))
+}
+
+impl PlyBook {
+    // Entries are (side to move's stones, played, score) and must all be at `ply`
+    pub fn from_entries(ply: i8, entries: &[(u64, u64, i8)]) -> Self {
+        let mut records: Vec<u64> = entries.iter()
+            .map(|&(player_squares, played, score)| canonical_key(player_squares, played) << 8 | score as u8 as u64)
+            .collect();
+        records.sort_unstable();
+        records.dedup_by_key(|record| *record >> 8);
+        PlyBook {ply, records: records.into_boxed_slice()}
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 5 || &bytes[..4] != PLY_BOOK_MAGIC {
+            return Err("not a ply book file".to_string());
+        }
+        let body = &bytes[5..];
+        if !body.len().is_multiple_of(8) {
+            return Err(format!("truncated ply book: {} bytes of records", body.len()));
+        }
+        let records: Box<[u64]> = body.chunks_exact(8)
+            .map(|record| u64::from_le_bytes(record.try_into().unwrap()))
+            .collect();
+        if records.windows(2).any(|pair| pair[0] >> 8 >= pair[1] >> 8) {
+            return Err("ply book records aren't sorted".to_string());
+        }
+        Ok(PlyBook {ply: bytes[4] as i8, records})
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|error| format!("couldn't read {}: {error}", path.display()))?;
+        Self::from_bytes(&bytes).map_err(|error| format!("{}: {error}", path.display()))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = PLY_BOOK_MAGIC.to_vec();
+        bytes.push(self.ply as u8);
+        for record in self.records.iter() {
+            bytes.extend(record.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn lookup(&self, player_squares: u64, played: u64) -> Option<i8> {
+        let key = canonical_key(player_squares, played);
+        let index = self.records.binary_search_by_key(&key, |record| record >> 8).ok()?;
+        Some(self.records[index] as u8 as i8)
+    }
+}
+
 const COL_ORDER: [usize;7]=[0,1,2,3,4,5,6];
 const REV_COL_ORDER: [usize;7]=[6,5,4,3,2,1,0];
 pub fn huffman_code(board_set:u64, board_p1: u64, reverse: bool)->i32{
@@ -0,0 +1,1328 @@
+use crate::game::*;
+use crate::engine::*;
+use crate::book::*;
+use crate::packed_book::pack;
+use crate::bench::*;
+use crate::selfplay::*;
+use crate::perft::perft;
+use crate::ordering::MoveOrdering;
+use crate::endgame::ENDGAME_EMPTY_SQUARES;
+use crate::tablebase::*;
+use crate::explorer::OpeningExplorer;
+use crate::annotate::*;
+use crate::record::{json_string, parse_records};
+use crate::puzzle::*;
+use crate::positions::*;
+use crate::training::*;
+use crate::evaluator::*;
+use crate::mlp::Mlp;
+use crate::random::Rng;
+use crate::{game_from_moves, parse_test_line, read_test_file, setup_game};
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::time::Instant;
+
+pub const EXIT_OK: i32 = 0;
+// The command ran but the answer was negative, e.g. an invalid position or a failed check
+pub const EXIT_FAILURE: i32 = 1;
+// Bad arguments or unusable input files
+pub const EXIT_USAGE: i32 = 2;
+
+const USAGE: &str = "usage: connect4enginebin [--json] [--tt-bits N] [--opening-book FILE] [--tablebase FILE] [--book FILE]... <command>
+
+commands:
+    solve <moves>             score of the position
+    analyze <moves>           score of every column
+    bestmove <moves>          best column and its score
+    pv <moves>                best line of play to the end of the game
+        --tie-break NAME      among equal moves the table's (tt), the centremost (centre) or the leftmost (left) (default tt)
+        --win NAME            play the fastest win and slowest loss (fastest) or any move keeping the result (any) (default fastest)
+    heuristic <moves>         estimated score of every column from a depth-limited search instead of solving
+        --depth D             plies searched, including the column's own move (default 8)
+        --model FILE          score the search's leaves with network weights instead of counting threats
+    annotate <moves>          grade every move of a game as best, inaccuracy or blunder
+        --from N              skip grading the first N moves (default 0)
+    record annotate <file>    annotate every game in a record file and write the records with scores and comments
+        --from N              skip grading the first N moves of each game (default 0)
+    explore <moves>           value of every continuation before ply 12 from the opening book
+    perft <ply>               move paths, unique positions and finished games for every ply up to <ply>
+    bench <testfile> [limit]  solve a file of \"moves score\" lines and report mean time and nodes
+    suite [dir] [options]     run every standard Pons test set in dir (default test_cases/fixtures)
+        --limit N             only solve the first N positions of each set
+        --save FILE           write the results as a JSON baseline
+        --compare FILE        fail if mean nodes grew beyond the threshold against a saved baseline
+        --threshold PCT       allowed node count growth in percent (default 5)
+        --order NAME          move ordering: threats, centre, history, killers or tt (default tt)
+        --endgame N           solve positions with N or fewer empty squares without the table (default 12)
+    fixtures [dir] [options]  generate small solver-scored test sets (default test_cases/fixtures)
+        --count N             positions per set (default 20)
+        --seed S              random seed (default 0)
+    match [options]           play two engine configurations against each other, swapping colours
+        --a SPEC, --b SPEC    engine settings such as tt=20,book=0,budget=100000,handicap=10
+                              or depth=8,model=FILE for a depth-limited heuristic search
+        --openings FILE       openings as 0-6 digit strings, one per line
+        --random N            use N seeded random openings instead (default 10)
+        --plies P             length of the random openings (default 8)
+        --balance S           only random openings the player to move scores from -S to S in (default 2)
+        --seed S              random seed (default 0)
+        --export FILE         write every game as \"moves player1 player2 result\"
+    positions [options]       print seeded random positions still in progress as \"moves [score]\" lines
+        --count N             number of positions (default 10)
+        --ply P               moves played in each (default 8)
+        --min-score S         only positions the player to move scores at least S in (searches each one)
+        --max-score S         only positions the player to move scores at most S in (searches each one)
+        --allow-wins          keep positions where the player to move can win at once
+        --seed S              random seed (default 0)
+    puzzles <file>            write \"win in N\" puzzles with a unique winning move, one per line
+        --count N             number of puzzles (default 20)
+        --min N, --max N      moves the win may take (default 1 to 4)
+        --source NAME         start from random games (playouts) or book positions (book) (default playouts)
+        --format NAME         space separated fields (line) or JSON objects (json) (default line)
+        --seed S              random seed (default 0)
+    export <dir> [options]    write solver-scored training samples as numbered NPZ shards
+        --samples N           total number of samples (default 10000)
+        --shard-size N        samples per shard (default 100000)
+        --source NAME         random games (random), book positions (book) or solver self-play (selfplay) (default random)
+        --min-ply P           fewest moves played in a sample (default 14)
+        --max-ply P           most moves played in a sample (default 30)
+        --shard K             only write shard K, e.g. to spread the shards over several machines
+        --seed S              random seed (default 0)
+    tablebase build <file>    write endgame scores for every position reachable from random roots
+        --empty K             empty squares at the roots (default 8)
+        --random N            number of random roots (default 1000)
+        --seed S              random seed (default 0)
+    stream                    solve \"moves [score]\" lines from stdin, writing \"moves score nodes micros\"
+    book probe <moves>        ply 12 book entry of a position, or how much of the book lies below it
+    book verify               check every opening book entry decodes and looks up correctly
+    book bench [count]        lookups per second of the packed book and an unpacked binary search (default 1000000)
+    book pack <dat> <file>    pack bookDeepDist style records into a file for --opening-book
+    book build <file>         solve random positions at one ply and write them as a book for --book
+        --ply P               ply of the book (default 16)
+        --random N            number of positions (default 1000)
+        --seed S              random seed (default 0)
+
+Moves are column digits 0-6 as accepted by c4engine. Test files use Pons' 1-7 notation.";
+
+pub struct Options {
+    pub json: bool,
+    pub tt_bits: usize,
+    // Packed ply 12 book memory mapped in place of the embedded one
+    pub opening_book: Option<String>,
+    // Endgame tablebase probed by every command that searches
+    pub tablebase: Option<String>,
+    // Books for other plies, used by the same commands
+    pub ply_books: Vec<String>,
+}
+
+pub fn run(args: Vec<String>) -> i32 {
+    let mut options = Options {
+        json: false,
+        tt_bits: 23,
+        opening_book: None,
+        tablebase: None,
+        ply_books: Vec::new(),
+    };
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => options.json = true,
+            "--tt-bits" => match args.next().and_then(|bits| bits.parse().ok()) {
+                Some(bits) if (MIN_TT_BITS..=32).contains(&bits) => options.tt_bits = bits,
+                _ => return usage_error(&format!("--tt-bits expects a number from {MIN_TT_BITS} to 32")),
+            },
+            "--opening-book" => match args.next() {
+                Some(path) => options.opening_book = Some(path),
+                None => return usage_error("--opening-book expects a file"),
+            },
+            "--tablebase" => match args.next() {
+                Some(path) => options.tablebase = Some(path),
+                None => return usage_error("--tablebase expects a file"),
+            },
+            "--book" => match args.next() {
+                Some(path) => options.ply_books.push(path),
+                None => return usage_error("--book expects a file"),
+            },
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return EXIT_OK;
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let positional: Vec<&str> = positional.iter().map(|arg| arg.as_str()).collect();
+    match positional.as_slice() {
+        ["solve", moves] => solve(moves, &options),
+        ["analyze", moves] => analyze(moves, &options),
+        ["bestmove", moves] => bestmove(moves, &options),
+        ["pv", moves, rest @ ..] => pv(moves, rest, &options),
+        ["heuristic", moves, rest @ ..] => heuristic(moves, rest, &options),
+        ["explore", moves] => explore(moves, &options),
+        ["annotate", rest @ ..] => annotate(rest, &options),
+        ["record", "annotate", rest @ ..] => record_annotate(rest, &options),
+        ["perft", plies] => match plies.parse() {
+            Ok(plies) if (0..=42).contains(&plies) => perft_command(plies, &options),
+            _ => usage_error("perft expects a ply from 0 to 42"),
+        },
+        ["bench", path] => bench(path, usize::MAX, &options),
+        ["bench", path, limit] => match limit.parse() {
+            Ok(limit) => bench(path, limit, &options),
+            _ => usage_error("bench limit must be a number"),
+        },
+        ["book", "probe", moves] => book_probe(moves, &options),
+        ["book", "verify"] => book_verify(&options),
+        ["book", "pack", records, path] => book_pack(records, path, &options),
+        ["book", "build", rest @ ..] => book_build(rest, &options),
+        ["book", "bench"] => book_bench(1_000_000, &options),
+        ["book", "bench", count] => match count.parse() {
+            Ok(count) => book_bench(count, &options),
+            _ => usage_error("book bench count must be a number"),
+        },
+        ["stream"] => stream(&options),
+        ["positions", rest @ ..] => positions(rest, &options),
+        ["puzzles", rest @ ..] => puzzles(rest, &options),
+        ["export", rest @ ..] => export(rest, &options),
+        ["tablebase", "build", rest @ ..] => tablebase_build(rest, &options),
+        ["suite", rest @ ..] => suite(rest, &options),
+        ["fixtures", rest @ ..] => fixtures(rest, &options),
+        ["match", rest @ ..] => self_play(rest, &options),
+        [] => usage_error("no command given"),
+        _ => usage_error(&format!("unknown command: {}", positional.join(" "))),
+    }
+}
+
+fn usage_error(message: &str) -> i32 {
+    eprintln!("error: {message}");
+    eprintln!("{USAGE}");
+    EXIT_USAGE
+}
+
+fn failure(message: &str, options: &Options) -> i32 {
+    if options.json {
+        println!("{{\"error\":{}}}", json_string(message));
+    } else {
+        eprintln!("error: {message}");
+    }
+    EXIT_FAILURE
+}
+
+// An input file the command can't use, reported like a failure but with the usage exit code
+fn input_error(message: &str, options: &Options) -> i32 {
+    failure(message, options);
+    EXIT_USAGE
+}
+
+// The embedded opening book, or the --opening-book file mapped in its place
+fn load_opening_book(options: &Options) -> Result<OpeningBook, i32> {
+    match &options.opening_book {
+        Some(path) => OpeningBook::open(Path::new(path)).map_err(|message| failure(&message, options)),
+        None => Ok(OpeningBook::new()),
+    }
+}
+
+// The opening book with any --tablebase and --book files attached
+fn load_book(options: &Options) -> Result<OpeningBook, i32> {
+    let mut book = load_opening_book(options)?;
+    if let Some(path) = &options.tablebase {
+        match Tablebase::load(Path::new(path)) {
+            Ok(tablebase) => book = book.with_tablebase(tablebase),
+            Err(message) => return Err(failure(&message, options)),
+        }
+    }
+    for path in &options.ply_books {
+        match PlyBook::load(Path::new(path)) {
+            Ok(ply_book) => book = book.with_ply_book(ply_book),
+            Err(message) => return Err(failure(&message, options)),
+        }
+    }
+    Ok(book)
+}
+
+fn parse_position(moves: &str, options: &Options) -> Result<Game, i32> {
+    if !moves.chars().all(|c| c.is_ascii_digit()) {
+        return Err(failure(&format!("moves must be column digits: {moves}"), options));
+    }
+    game_from_moves(moves).ok_or_else(|| failure(&format!("illegal move sequence: {moves}"), options))
+}
+
+fn json_score(score: Option<i8>) -> String {
+    match score {
+        Some(score) => score.to_string(),
+        None => "null".to_string(),
+    }
+}
+
+// --flag value pairs, with the leading dashes removed from the flag
+type Flags<'a> = Vec<(&'a str, &'a str)>;
+
+// Splits command arguments into an optional leading directory and --flag value pairs
+fn parse_flags<'a>(args: &[&'a str], flags: &[&str]) -> Result<(Option<&'a str>, Flags<'a>), String> {
+    let mut dir = None;
+    let mut values = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(flag) = arg.strip_prefix("--") {
+            if !flags.contains(&flag) {
+                return Err(format!("unknown option --{flag}"));
+            }
+            let value = args.next().ok_or_else(|| format!("--{flag} expects a value"))?;
+            values.push((flag, *value));
+        } else if dir.is_none() {
+            dir = Some(*arg);
+        } else {
+            return Err(format!("unexpected argument {arg}"));
+        }
+    }
+    Ok((dir, values))
+}
+
+fn solve(moves: &str, options: &Options) -> i32 {
+    let mut game = match parse_position(moves, options) {
+        Ok(game) => game,
+        Err(code) => return code,
+    };
+    let mut table = TranspositionTable::new(options.tt_bits);
+    let book = match load_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    let mut nodes = 0;
+
+    let start = Instant::now();
+    let score = search(&mut game, &mut table, &book, &mut nodes);
+    let micros = start.elapsed().as_micros();
+    if options.json {
+        println!("{{\"moves\":\"{moves}\",\"score\":{score},\"nodes\":{nodes},\"micros\":{micros}}}");
+    } else {
+        println!("{score}");
+    }
+    EXIT_OK
+}
+
+fn analyze(moves: &str, options: &Options) -> i32 {
+    let mut game = match parse_position(moves, options) {
+        Ok(game) => game,
+        Err(code) => return code,
+    };
+    let mut table = TranspositionTable::new(options.tt_bits);
+    let book = match load_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    let mut nodes = 0;
+
+    let scores = column_scores(&mut game, &mut table, &book, &mut nodes);
+    if options.json {
+        let scores: Vec<String> = scores.iter().map(|score| json_score(*score)).collect();
+        println!("{{\"moves\":\"{moves}\",\"scores\":[{}],\"nodes\":{nodes}}}", scores.join(","));
+    } else {
+        for (col_num, score) in scores.iter().enumerate() {
+            match score {
+                Some(score) => println!("{col_num}: {score}"),
+                None => println!("{col_num}: -"),
+            }
+        }
+    }
+    EXIT_OK
+}
+
+fn bestmove(moves: &str, options: &Options) -> i32 {
+    let mut game = match parse_position(moves, options) {
+        Ok(game) => game,
+        Err(code) => return code,
+    };
+    let mut table = TranspositionTable::new(options.tt_bits);
+    let book = match load_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    let mut nodes = 0;
+
+    match best_move(&mut game, &mut table, &book, &mut nodes) {
+        Some((col_num, score)) => {
+            if options.json {
+                println!("{{\"moves\":\"{moves}\",\"column\":{col_num},\"score\":{score},\"nodes\":{nodes}}}");
+            } else {
+                println!("{col_num} {score}");
+            }
+            EXIT_OK
+        }
+        None => failure("the game is already over", options),
+    }
+}
+
+fn pv(moves: &str, args: &[&str], options: &Options) -> i32 {
+    let (extra, flags) = match parse_flags(args, &["tie-break", "win"]) {
+        Ok(parsed) => parsed,
+        Err(message) => return usage_error(&message),
+    };
+    if let Some(extra) = extra {
+        return usage_error(&format!("unexpected argument {extra}"));
+    }
+    let mut pv_options = PvOptions::default();
+    for (flag, value) in flags {
+        match (flag, value) {
+            ("tie-break", name) => match TieBreak::from_name(name) {
+                Some(tie_break) => pv_options.tie_break = tie_break,
+                None => return usage_error("--tie-break expects tt, centre or left"),
+            },
+            (_, "fastest") => pv_options.fastest_win = true,
+            (_, "any") => pv_options.fastest_win = false,
+            _ => return usage_error("--win expects fastest or any"),
+        }
+    }
+    let mut game = match parse_position(moves, options) {
+        Ok(game) => game,
+        Err(code) => return code,
+    };
+    if game.game_status != GameStatus::InProgress {
+        return failure("the game is already over", options);
+    }
+    let mut table = TranspositionTable::new(options.tt_bits);
+    let book = match load_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    let mut nodes = 0;
+
+    let line: String = principal_variation_with(&mut game, &mut table, &book, &mut nodes, pv_options).iter()
+        .map(|col_num| col_num.to_string())
+        .collect();
+    if options.json {
+        println!("{{\"moves\":\"{moves}\",\"line\":\"{line}\",\"nodes\":{nodes}}}");
+    } else {
+        println!("{line}");
+    }
+    EXIT_OK
+}
+
+fn heuristic(moves: &str, args: &[&str], options: &Options) -> i32 {
+    let (extra, flags) = match parse_flags(args, &["depth", "model"]) {
+        Ok(parsed) => parsed,
+        Err(message) => return usage_error(&message),
+    };
+    if let Some(extra) = extra {
+        return usage_error(&format!("unexpected argument {extra}"));
+    }
+    let mut depth = 8;
+    let mut evaluator: Box<dyn Evaluator> = Box::new(ThreatEvaluator);
+    for (flag, value) in flags {
+        match (flag, value) {
+            ("model", path) => match Mlp::load(Path::new(path)) {
+                Ok(model) => evaluator = Box::new(model),
+                Err(message) => return failure(&message, options),
+            },
+            _ => match value.parse::<u8>() {
+                Ok(value) if (1..=42).contains(&value) => depth = value,
+                _ => return usage_error("--depth expects a number from 1 to 42"),
+            },
+        }
+    }
+    let mut game = match parse_position(moves, options) {
+        Ok(game) => game,
+        Err(code) => return code,
+    };
+    let mut nodes = 0;
+
+    let start = Instant::now();
+    let scores = heuristic_column_scores(&mut game, evaluator.as_mut(), depth, &mut nodes);
+    let micros = start.elapsed().as_micros();
+    if options.json {
+        let scores: Vec<String> = scores.iter().map(|score| score.map_or("null".to_string(), |score| score.to_string())).collect();
+        println!("{{\"moves\":\"{moves}\",\"depth\":{depth},\"scores\":[{}],\"nodes\":{nodes},\"micros\":{micros}}}", scores.join(","));
+    } else {
+        for (col_num, score) in scores.iter().enumerate() {
+            match score {
+                Some(score) => println!("{col_num}: {score:.2}"),
+                None => println!("{col_num}: -"),
+            }
+        }
+    }
+    EXIT_OK
+}
+
+// The positional argument and --from of annotate and record annotate
+fn annotate_args<'a>(args: &[&'a str], command: &str) -> Result<(&'a str, usize), String> {
+    let (argument, flags) = parse_flags(args, &["from"])?;
+    let argument = argument.ok_or_else(|| format!("{command} expects an argument"))?;
+    let mut first = 0;
+    for (flag, value) in flags {
+        first = value.parse().map_err(|_| format!("--{flag} expects a number"))?;
+    }
+    Ok((argument, first))
+}
+
+fn annotate(args: &[&str], options: &Options) -> i32 {
+    let (moves, first) = match annotate_args(args, "annotate") {
+        Ok(parsed) => parsed,
+        Err(message) => return usage_error(&message),
+    };
+    if !moves.chars().all(|c| c.is_ascii_digit()) {
+        return failure(&format!("moves must be column digits: {moves}"), options);
+    }
+    let moves: Vec<u8> = moves.bytes().map(|digit| digit - b'0').collect();
+    let mut table = TranspositionTable::new(options.tt_bits);
+    let book = match load_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    let mut nodes = 0;
+    let start = Instant::now();
+
+    let annotations = match annotate_moves(&moves, first, &mut table, &book, &mut nodes) {
+        Ok(annotations) => annotations,
+        Err(message) => return failure(&message, options),
+    };
+    let millis = start.elapsed().as_millis();
+    if options.json {
+        let annotations: Vec<String> = annotations.iter().map(|annotation| annotation.to_json()).collect();
+        println!("{{\"moves\":[{}],\"nodes\":{nodes},\"millis\":{millis}}}", annotations.join(","));
+    } else {
+        for (i, annotation) in annotations.iter().enumerate() {
+            let best_move = annotation.best_move.map_or(String::new(), |col_num| format!(", {col_num} scores {}", annotation.best_score));
+            println!("{:>2}. {} {:+} {}{best_move}", first + i + 1, annotation.col_num, annotation.score, annotation.quality.name());
+        }
+    }
+    EXIT_OK
+}
+
+fn record_annotate(args: &[&str], options: &Options) -> i32 {
+    let (path, first) = match annotate_args(args, "record annotate") {
+        Ok(parsed) => parsed,
+        Err(message) => return usage_error(&message),
+    };
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) => return failure(&format!("couldn't read {path}: {error}"), options),
+    };
+    let mut records = match parse_records(&text) {
+        Ok(records) => records,
+        Err(message) => return failure(&format!("{path}: {message}"), options),
+    };
+    let mut table = TranspositionTable::new(options.tt_bits);
+    let book = match load_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    let mut nodes = 0;
+    for (i, record) in records.iter_mut().enumerate() {
+        if let Err(message) = annotate_record(record, first, &mut table, &book, &mut nodes) {
+            return failure(&format!("{path}: game {}: {message}", i + 1), options);
+        }
+        if options.json {
+            println!("{}", record.to_json());
+        } else {
+            if i > 0 {
+                println!();
+            }
+            print!("{}", record.to_text());
+        }
+    }
+    EXIT_OK
+}
+
+fn explore(moves: &str, options: &Options) -> i32 {
+    let mut game = match parse_position(moves, options) {
+        Ok(game) => game,
+        Err(code) => return code,
+    };
+    let mut table = TranspositionTable::new(options.tt_bits);
+    let book = match load_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    let mut nodes = 0;
+    let start = Instant::now();
+
+    let Some(exploration) = OpeningExplorer::new().explore(&mut game, &mut table, &book, &mut nodes) else {
+        return failure(&format!("explore needs a game in progress before ply {BOOK_PLY}"), options);
+    };
+    let millis = start.elapsed().as_millis();
+    if options.json {
+        println!("{{\"moves\":{},\"exploration\":{},\"nodes\":{nodes},\"millis\":{millis}}}",
+            json_string(moves), exploration.to_json());
+    } else {
+        for continuation in &exploration.continuations {
+            let searched = if continuation.searched {" (searched)"} else {""};
+            println!("{} {}{searched}", continuation.col_num, continuation.score);
+        }
+        println!("{} ply {BOOK_PLY} positions missing from the book were searched live", exploration.fallbacks.len());
+    }
+    EXIT_OK
+}
+
+fn perft_command(plies: i8, options: &Options) -> i32 {
+    let start = Instant::now();
+    let stats = perft(plies);
+    let micros = start.elapsed().as_micros();
+    let mismatched = stats.iter().filter(|ply_stats| ply_stats.matches_published() == Some(false)).count();
+
+    if options.json {
+        let stats: Vec<String> = stats.iter().map(|ply_stats| ply_stats.to_json()).collect();
+        println!("{{\"plies\":[{}],\"mismatched\":{mismatched},\"micros\":{micros}}}", stats.join(","));
+    } else {
+        println!("{:>4}{:>14}{:>12}{:>12}{:>10}{:>10}{:>8}  published", "ply", "paths", "positions", "mirrored", "p1 wins", "p2 wins", "draws");
+        for ply_stats in &stats {
+            let check = match ply_stats.matches_published() {
+                Some(true) => "ok",
+                Some(false) => "MISMATCH",
+                None => "-",
+            };
+            println!("{:>4}{:>14}{:>12}{:>12}{:>10}{:>10}{:>8}  {check}", ply_stats.ply, ply_stats.paths, ply_stats.positions,
+                ply_stats.mirror_positions, ply_stats.player1_wins, ply_stats.player2_wins, ply_stats.draws);
+        }
+    }
+    if mismatched > 0 {EXIT_FAILURE} else {EXIT_OK}
+}
+
+fn bench(path: &str, limit: usize, options: &Options) -> i32 {
+    let (test_moves, test_evals) = match read_test_file(path) {
+        Ok(test_file) => test_file,
+        Err(message) => return input_error(&message, options),
+    };
+    let mut table = TranspositionTable::new(options.tt_bits);
+    let book = match load_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    let mut nodes = 0;
+    let mut failed = 0;
+
+    let count = test_moves.len().min(limit);
+    let start = Instant::now();
+    for i in 0..count {
+        let mut game = Game::new();
+        if let Err(message) = setup_game(&mut game, &test_moves[i]) {
+            return input_error(&format!("{path}: {message}"), options);
+        }
+        let eval = search(&mut game, &mut table, &book, &mut nodes);
+        if eval != test_evals[i] {
+            eprintln!("test {i} failed: eval {eval}, answer {}", test_evals[i]);
+            failed += 1;
+        }
+    }
+    let time_taken = start.elapsed();
+    let divisor = count.max(1);
+
+    if options.json {
+        println!("{{\"file\":{},\"positions\":{count},\"failed\":{failed},\"mean_micros\":{},\"mean_nodes\":{}}}",
+            json_string(path), time_taken.as_micros() / divisor as u128, nodes / divisor as u64);
+    } else {
+        println!("Mean Time Taken: {:#?}", time_taken / divisor as u32);
+        println!("Mean Nodes: {:#?}", nodes / divisor as u64);
+        if failed > 0 {
+            println!("Failed: {failed}");
+        }
+    }
+    if failed > 0 {EXIT_FAILURE} else {EXIT_OK}
+}
+
+// Binary search over the book unpacked into memory, which lookups in the packed book must agree with
+fn find_unpacked(entries: &[(i32, i8)], code: i32) -> Option<i8> {
+    entries.binary_search_by_key(&code, |&(entry_code, _)| entry_code).ok().map(|i| entries[i].1)
+}
+
+fn book_probe(moves: &str, options: &Options) -> i32 {
+    let game = match parse_position(moves, options) {
+        Ok(game) => game,
+        Err(code) => return code,
+    };
+    let book = match load_opening_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    let entry = book.lookup_entry(game.board_set, game.board_p1);
+    let coverage = book.coverage(game.board_set, game.board_p1);
+    if options.json {
+        println!("{{\"moves\":{},\"entry\":{},\"coverage\":{}}}", json_string(moves),
+            entry.map_or("null".to_string(), |entry| entry.to_json()),
+            coverage.map_or("null".to_string(), |coverage| coverage.to_json()));
+        return EXIT_OK;
+    }
+    match entry {
+        Some(entry) => {
+            println!("entry {} code {}{}", entry.index, entry.code, if entry.mirrored {" (mirrored)"} else {""});
+            println!("stored byte {}, score {}{}", entry.raw, entry.eval, if entry.corrected {" (corrected)"} else {""});
+        }
+        None => println!("not in the book"),
+    }
+    if let Some(coverage) = coverage {
+        println!("ply {BOOK_PLY} positions below: {}, in the book: {}, games ended before: {}",
+            coverage.descendants, coverage.covered, coverage.finished);
+    }
+    EXIT_OK
+}
+
+fn book_verify(options: &Options) -> i32 {
+    let book = match load_opening_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    let entries: Vec<(i32, i8)> = book.entries().collect();
+    let mut decode_failures = 0;
+    let mut lookup_failures = 0;
+    if entries.len() != book.len() || entries.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+        return failure("packed book entries are missing or out of order", options);
+    }
+    for &(code, eval) in &entries {
+        let (set, p1) = decode(code);
+        if huffman_code(set, p1, false) != code {
+            decode_failures += 1;
+        }
+        // Also check the mirror image, which is usually a miss
+        let mirror_code = huffman_code(set, p1, true);
+        if book.lookup(set, p1) != Some(eval)
+            || book.find(code) != Some(eval)
+            || book.find(mirror_code) != find_unpacked(&entries, mirror_code) {
+            lookup_failures += 1;
+        }
+    }
+
+    if options.json {
+        println!("{{\"entries\":{},\"decode_failures\":{decode_failures},\"lookup_failures\":{lookup_failures}}}",
+            book.len());
+    } else {
+        println!("entries: {}", book.len());
+        println!("decode failures: {decode_failures}");
+        println!("lookup failures: {lookup_failures}");
+    }
+    if decode_failures + lookup_failures > 0 {EXIT_FAILURE} else {EXIT_OK}
+}
+
+// Solves positions from stdin as they arrive, keeping one transposition table warm for the whole stream.
+// Malformed lines are reported on stderr and produce an empty output line so the output stays aligned with the input.
+fn stream(options: &Options) -> i32 {
+    let mut table = TranspositionTable::new(options.tt_bits);
+    let book = match load_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    let stdin = std::io::stdin();
+    let mut stdin = stdin.lock();
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    let mut malformed = 0;
+    let mut buffer = Vec::new();
+
+    for line_number in 1.. {
+        buffer.clear();
+        match stdin.read_until(b'\n', &mut buffer) {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(error) => {
+                eprintln!("line {line_number}: {error}");
+                return EXIT_FAILURE;
+            }
+        }
+        let line = String::from_utf8_lossy(&buffer);
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let output = match stream_position(line, &mut table, &book) {
+            Ok((score, nodes, micros)) => {
+                let moves = line.split_whitespace().next().unwrap_or_default();
+                if options.json {
+                    format!("{{\"moves\":{},\"score\":{score},\"nodes\":{nodes},\"micros\":{micros}}}", json_string(moves))
+                } else {
+                    format!("{moves} {score} {nodes} {micros}")
+                }
+            }
+            Err(message) => {
+                eprintln!("line {line_number}: {message} \"{line}\"");
+                malformed += 1;
+                if options.json {
+                    format!("{{\"error\":{}}}", json_string(&message))
+                } else {
+                    String::new()
+                }
+            }
+        };
+        if writeln!(stdout, "{output}").and_then(|_| stdout.flush()).is_err() {
+            // The reader went away, e.g. the output was piped into head
+            return EXIT_OK;
+        }
+    }
+    if malformed > 0 {EXIT_FAILURE} else {EXIT_OK}
+}
+
+fn stream_position(line: &str, table: &mut TranspositionTable, book: &OpeningBook) -> Result<(i8, u64, u128), String> {
+    let (moves, _) = parse_test_line(line)?;
+    let mut game = Game::new();
+    for (i, col_num) in moves.iter().enumerate() {
+        if let (false, _) = game.make_move(*col_num) {
+            return Err(format!("invalid move {}", i+1));
+        }
+    }
+    let mut nodes = 0;
+    let start = Instant::now();
+    let score = search(&mut game, table, book, &mut nodes);
+    Ok((score, nodes, start.elapsed().as_micros()))
+}
+
+fn suite(args: &[&str], options: &Options) -> i32 {
+    let (dir, flags) = match parse_flags(args, &["limit", "save", "compare", "threshold", "order", "endgame"]) {
+        Ok(parsed) => parsed,
+        Err(message) => return usage_error(&message),
+    };
+    let mut limit = usize::MAX;
+    let mut save = None;
+    let mut baseline = None;
+    let mut threshold = 5.0;
+    let mut ordering = MoveOrdering::TableMove;
+    let mut endgame_empty = ENDGAME_EMPTY_SQUARES;
+    for (flag, value) in flags {
+        match flag {
+            "limit" => match value.parse() {
+                Ok(value) => limit = value,
+                Err(_) => return usage_error("--limit expects a number"),
+            },
+            "threshold" => match value.parse() {
+                Ok(value) => threshold = value,
+                Err(_) => return usage_error("--threshold expects a percentage"),
+            },
+            "order" => match MoveOrdering::from_name(value) {
+                Some(value) => ordering = value,
+                None => return usage_error("--order expects threats, centre, history, killers or tt"),
+            },
+            "endgame" => match value.parse() {
+                Ok(value) if (0..=42).contains(&value) => endgame_empty = value,
+                _ => return usage_error("--endgame expects a number of empty squares from 0 to 42"),
+            },
+            "save" => save = Some(value),
+            _ => baseline = Some(value),
+        }
+    }
+    let baseline = match baseline.map(|path| read_baseline(Path::new(path))) {
+        Some(Ok(baseline)) => Some(baseline),
+        Some(Err(message)) => return failure(&message, options),
+        None => None,
+    };
+
+    let book = match load_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    let results = match run_suite(Path::new(dir.unwrap_or("test_cases/fixtures")), limit, options.tt_bits, &book, ordering, endgame_empty) {
+        Ok(results) => results,
+        Err(message) => return input_error(&message, options),
+    };
+    if results.is_empty() {
+        return failure("no test sets found", options);
+    }
+    if let Some(path) = save {
+        if let Err(error) = std::fs::write(path, suite_json(&results)) {
+            return failure(&format!("couldn't write {path}: {error}"), options);
+        }
+    }
+
+    if options.json {
+        print!("{}", suite_json(&results));
+    } else {
+        println!("{:<12}{:>8}{:>8}{:>12}{:>12}{:>12}{:>12}{:>12}{:>12}",
+            "set", "count", "failed", "mean us", "median us", "p99 us", "mean nodes", "median", "p99");
+        for result in &results {
+            println!("{:<12}{:>8}{:>8}{:>12}{:>12}{:>12}{:>12}{:>12}{:>12}",
+                result.name, result.positions, result.failed, result.mean_micros, result.median_micros, result.p99_micros,
+                result.mean_nodes, result.median_nodes, result.p99_nodes);
+        }
+    }
+
+    let regressions = compare(&results, baseline.as_deref().unwrap_or_default(), threshold);
+    for regression in &regressions {
+        eprintln!("regression: {regression}");
+    }
+    if regressions.is_empty() {EXIT_OK} else {EXIT_FAILURE}
+}
+
+fn fixtures(args: &[&str], options: &Options) -> i32 {
+    let (dir, flags) = match parse_flags(args, &["count", "seed"]) {
+        Ok(parsed) => parsed,
+        Err(message) => return usage_error(&message),
+    };
+    let mut count = 20;
+    let mut seed = 0;
+    for (flag, value) in flags {
+        let parsed = value.parse();
+        match (flag, parsed) {
+            ("count", Ok(value)) => count = value as usize,
+            ("seed", Ok(value)) => seed = value,
+            _ => return usage_error(&format!("--{flag} expects a number")),
+        }
+    }
+    let dir = dir.unwrap_or("test_cases/fixtures");
+    let book = match load_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    match generate_fixtures(Path::new(dir), count, seed, options.tt_bits, &book) {
+        Ok(()) => EXIT_OK,
+        Err(message) => failure(&message, options),
+    }
+}
+
+// Times lookups of book entries mixed with their mirror images, most of which aren't in the book,
+// against a binary search over the book unpacked into memory
+fn book_bench(count: usize, options: &Options) -> i32 {
+    let start = Instant::now();
+    let book = match load_opening_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    let load_micros = start.elapsed().as_micros();
+    if book.is_empty() {
+        return failure("the opening book is empty", options);
+    }
+    let entries: Vec<(i32, i8)> = book.entries().collect();
+    let mut rng = Rng::new(0);
+    let codes: Vec<i32> = (0..count)
+        .map(|_| {
+            let code = entries[rng.below(entries.len() as u64) as usize].0;
+            if rng.below(2) == 0 {
+                code
+            } else {
+                let (set, p1) = decode(code);
+                huffman_code(set, p1, true)
+            }
+        })
+        .collect();
+
+    let time = |find: &dyn Fn(i32) -> Option<i8>| {
+        let start = Instant::now();
+        let hits = codes.iter().filter(|&&code| find(code).is_some()).count();
+        (hits, count as f64 / start.elapsed().as_secs_f64().max(1e-9))
+    };
+    let (hits, packed) = time(&|code| book.find(code));
+    let (reference_hits, reference) = time(&|code| find_unpacked(&entries, code));
+    if hits != reference_hits {
+        return failure(&format!("packed book found {hits} entries, unpacked binary search {reference_hits}"), options);
+    }
+    let packed_bytes = book.packed_size();
+    let unpacked_bytes = 5 * entries.len();
+    if options.json {
+        println!("{{\"lookups\":{count},\"hits\":{hits},\"packed_per_second\":{packed:.0},\"reference_per_second\":{reference:.0},\"packed_bytes\":{packed_bytes},\"unpacked_bytes\":{unpacked_bytes},\"load_micros\":{load_micros}}}");
+    } else {
+        println!("{count} lookups, {hits} hits");
+        println!("packed: {packed:.0} lookups/s");
+        println!("unpacked binary search: {reference:.0} lookups/s");
+        println!("size: {packed_bytes} bytes packed, {unpacked_bytes} unpacked, loaded in {load_micros} us");
+    }
+    EXIT_OK
+}
+
+fn book_pack(records: &str, path: &str, options: &Options) -> i32 {
+    let records = match std::fs::read(records) {
+        Ok(records) => records,
+        Err(error) => return failure(&format!("couldn't read {records}: {error}"), options),
+    };
+    if records.len() % 5 != 0 {
+        return failure("book records are 5 bytes each", options);
+    }
+    let codes: Vec<i32> = records.chunks_exact(5)
+        .map(|record| i32::from_be_bytes([record[0], record[1], record[2], record[3]]))
+        .collect();
+    if codes.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return failure("book records must be sorted by code", options);
+    }
+    let packed = pack(&records);
+    if let Err(error) = std::fs::write(path, &packed) {
+        return failure(&format!("couldn't write {path}: {error}"), options);
+    }
+    if options.json {
+        println!("{{\"file\":{},\"entries\":{},\"bytes\":{}}}", json_string(path), records.len() / 5, packed.len());
+    } else {
+        println!("{} entries packed into {} bytes in {path}", records.len() / 5, packed.len());
+    }
+    EXIT_OK
+}
+
+fn book_build(args: &[&str], options: &Options) -> i32 {
+    let (path, flags) = match parse_flags(args, &["ply", "random", "seed"]) {
+        Ok(parsed) => parsed,
+        Err(message) => return usage_error(&message),
+    };
+    let Some(path) = path else {
+        return usage_error("book build expects an output file");
+    };
+    let mut ply = 16;
+    let mut random = 1000;
+    let mut seed = 0;
+    for (flag, value) in flags {
+        match (flag, value.parse::<u64>()) {
+            ("ply", Ok(value)) if value < 42 => ply = value as i8,
+            ("random", Ok(value)) => random = value as usize,
+            ("seed", Ok(value)) => seed = value,
+            _ => return usage_error(&format!("--{flag} expects a number")),
+        }
+    }
+
+    let book = match load_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    let mut table = TranspositionTable::new(options.tt_bits);
+    let mut nodes = 0;
+    let start = Instant::now();
+    let entries: Vec<(u64, u64, i8)> = random_positions(random, &PositionOptions {ply, exclude_immediate_wins: true}, seed).iter()
+        .map(|moves| {
+            let mut game = game_from_moves(moves).expect("random openings are legal");
+            let score = search(&mut game, &mut table, &book, &mut nodes);
+            let player_squares = if game.player_one_turn {game.board_set & game.board_p1} else {game.board_set & !game.board_p1};
+            (player_squares, game.board_set, score)
+        })
+        .collect();
+    let ply_book = PlyBook::from_entries(ply, &entries);
+    if let Err(error) = std::fs::write(path, ply_book.to_bytes()) {
+        return failure(&format!("couldn't write {path}: {error}"), options);
+    }
+    let millis = start.elapsed().as_millis();
+    if options.json {
+        println!("{{\"file\":{},\"ply\":{ply},\"positions\":{},\"nodes\":{nodes},\"millis\":{millis}}}",
+            json_string(path), ply_book.len());
+    } else {
+        println!("{} positions at ply {ply} written to {path} in {millis} ms", ply_book.len());
+    }
+    EXIT_OK
+}
+
+fn positions(args: &[&str], options: &Options) -> i32 {
+    // --allow-wins takes no value, so it's picked out before the valued flags
+    let allow_wins = args.contains(&"--allow-wins");
+    let args: Vec<&str> = args.iter().copied().filter(|&arg| arg != "--allow-wins").collect();
+    let (extra, flags) = match parse_flags(&args, &["count", "ply", "min-score", "max-score", "seed"]) {
+        Ok(parsed) => parsed,
+        Err(message) => return usage_error(&message),
+    };
+    if let Some(extra) = extra {
+        return usage_error(&format!("unexpected argument {extra}"));
+    }
+    let mut count = 10;
+    let mut position_options = PositionOptions {exclude_immediate_wins: !allow_wins, ..Default::default()};
+    let mut score_range: Option<(i8, i8)> = None;
+    let mut seed = 0;
+    for (flag, value) in flags {
+        match (flag, value.parse::<i64>()) {
+            ("count", Ok(value)) if value >= 0 => count = value as usize,
+            ("ply", Ok(value)) if (0..42).contains(&value) => position_options.ply = value as i8,
+            ("min-score", Ok(value)) if (-21..=21).contains(&value) => {
+                score_range = Some((value as i8, score_range.map_or(21, |(_, max_score)| max_score)));
+            }
+            ("max-score", Ok(value)) if (-21..=21).contains(&value) => {
+                score_range = Some((score_range.map_or(-21, |(min_score, _)| min_score), value as i8));
+            }
+            ("seed", Ok(value)) if value >= 0 => seed = value as u64,
+            _ => return usage_error(&format!("--{flag} expects a number in range")),
+        }
+    }
+
+    let positions: Vec<(String, Option<i8>)> = match score_range {
+        Some((min_score, max_score)) => {
+            let book = match load_book(options) {
+                Ok(book) => book,
+                Err(code) => return code,
+            };
+            let mut table = TranspositionTable::new(options.tt_bits);
+            let mut nodes = 0;
+            random_scored_positions(count, &position_options, min_score, max_score, seed, &mut table, &book, &mut nodes)
+                .into_iter().map(|(moves, score)| (moves, Some(score))).collect()
+        }
+        None => random_positions(count, &position_options, seed).into_iter().map(|moves| (moves, None)).collect(),
+    };
+    for (moves, score) in &positions {
+        match (options.json, score) {
+            (true, Some(score)) => println!("{{\"moves\":\"{moves}\",\"score\":{score}}}"),
+            (true, None) => println!("{{\"moves\":\"{moves}\"}}"),
+            (false, Some(score)) => println!("{moves} {score}"),
+            (false, None) => println!("{moves}"),
+        }
+    }
+    if positions.len() < count {
+        return failure(&format!("only {} positions met the options", positions.len()), options);
+    }
+    EXIT_OK
+}
+
+fn puzzles(args: &[&str], options: &Options) -> i32 {
+    let (path, flags) = match parse_flags(args, &["count", "min", "max", "source", "format", "seed"]) {
+        Ok(parsed) => parsed,
+        Err(message) => return usage_error(&message),
+    };
+    let Some(path) = path else {
+        return usage_error("puzzles expects an output file");
+    };
+    let mut count = 20;
+    let mut min_win_in = 1;
+    let mut max_win_in = 4;
+    let mut source = PuzzleSource::Playouts;
+    let mut jsonl = false;
+    let mut seed = 0;
+    for (flag, value) in flags {
+        match (flag, value) {
+            ("source", "playouts") => source = PuzzleSource::Playouts,
+            ("source", "book") => source = PuzzleSource::Book,
+            ("source", _) => return usage_error("--source expects playouts or book"),
+            ("format", "line") => jsonl = false,
+            ("format", "json") => jsonl = true,
+            ("format", _) => return usage_error("--format expects line or json"),
+            _ => match (flag, value.parse::<u64>()) {
+                ("count", Ok(value)) => count = value as usize,
+                ("min" | "max", Ok(value)) if !(1..=MAX_WIN_IN as u64).contains(&value) => {
+                    return usage_error(&format!("--{flag} expects a number of moves from 1 to {MAX_WIN_IN}"));
+                }
+                ("min", Ok(value)) => min_win_in = value as u8,
+                ("max", Ok(value)) => max_win_in = value as u8,
+                ("seed", Ok(value)) => seed = value,
+                _ => return usage_error(&format!("--{flag} expects a number")),
+            },
+        }
+    }
+    if min_win_in > max_win_in {
+        return usage_error("--min can't be above --max");
+    }
+
+    let book = match load_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    let mut table = TranspositionTable::new(options.tt_bits);
+    let mut nodes = 0;
+    let start = Instant::now();
+    let puzzles = match generate_puzzles(count, min_win_in, max_win_in, source, seed, &mut table, &book, &mut nodes) {
+        Ok(puzzles) => puzzles,
+        Err(message) => return failure(&message, options),
+    };
+    let lines: String = puzzles.iter()
+        .map(|puzzle| if jsonl {puzzle.to_json()} else {puzzle.to_line()} + "\n")
+        .collect();
+    if let Err(error) = std::fs::write(path, lines) {
+        return failure(&format!("couldn't write {path}: {error}"), options);
+    }
+    let millis = start.elapsed().as_millis();
+    let per_difficulty: Vec<(Difficulty, usize)> = Difficulty::ALL.iter()
+        .map(|&difficulty| (difficulty, puzzles.iter().filter(|puzzle| puzzle.difficulty == difficulty).count()))
+        .collect();
+    if options.json {
+        let per_difficulty: Vec<String> = per_difficulty.iter().map(|(difficulty, count)| format!("\"{}\":{count}", difficulty.name())).collect();
+        println!("{{\"file\":{},\"puzzles\":{},\"difficulty\":{{{}}},\"nodes\":{nodes},\"millis\":{millis}}}",
+            json_string(path), puzzles.len(), per_difficulty.join(","));
+    } else {
+        let per_difficulty: Vec<String> = per_difficulty.iter().map(|(difficulty, count)| format!("{count} {}", difficulty.name())).collect();
+        println!("{} puzzles written to {path} in {millis} ms: {}", puzzles.len(), per_difficulty.join(", "));
+    }
+    EXIT_OK
+}
+
+fn export(args: &[&str], options: &Options) -> i32 {
+    let (dir, flags) = match parse_flags(args, &["samples", "shard-size", "source", "min-ply", "max-ply", "shard", "seed"]) {
+        Ok(parsed) => parsed,
+        Err(message) => return usage_error(&message),
+    };
+    let Some(dir) = dir else {
+        return usage_error("export expects an output directory");
+    };
+    let mut samples = 10_000;
+    let mut only_shard = None;
+    let mut export_options = ExportOptions::default();
+    for (flag, value) in flags {
+        match (flag, value) {
+            ("source", name) => match SampleSource::from_name(name) {
+                Some(source) => export_options.source = source,
+                None => return usage_error("--source expects random, book or selfplay"),
+            },
+            _ => match (flag, value.parse::<u64>()) {
+                ("samples", Ok(value)) => samples = value as usize,
+                ("shard-size", Ok(value)) if value > 0 => export_options.shard_size = value as usize,
+                ("min-ply", Ok(value)) if value < 42 => export_options.min_ply = value as i8,
+                ("max-ply", Ok(value)) if value < 42 => export_options.max_ply = value as i8,
+                ("shard", Ok(value)) => only_shard = Some(value as usize),
+                ("seed", Ok(value)) => export_options.seed = value,
+                _ => return usage_error(&format!("--{flag} expects a number in range")),
+            },
+        }
+    }
+    if export_options.min_ply > export_options.max_ply {
+        return usage_error("--min-ply can't be above --max-ply");
+    }
+    let shard_sizes = shard_sizes(samples, export_options.shard_size);
+    if only_shard.is_some_and(|shard| shard >= shard_sizes.len()) {
+        return usage_error(&format!("--shard must be below the {} shards", shard_sizes.len()));
+    }
+
+    let book = match load_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    if let Err(error) = std::fs::create_dir_all(dir) {
+        return failure(&format!("couldn't create {dir}: {error}"), options);
+    }
+    let mut table = TranspositionTable::new(options.tt_bits);
+    let mut nodes = 0;
+    let start = Instant::now();
+    let mut written = Vec::new();
+    for (shard, &count) in shard_sizes.iter().enumerate() {
+        if only_shard.is_some_and(|only_shard| only_shard != shard) {
+            continue;
+        }
+        let samples = match generate_shard(shard, count, &export_options, &mut table, &book, &mut nodes) {
+            Ok(samples) => samples,
+            Err(message) => return failure(&message, options),
+        };
+        let path = Path::new(dir).join(shard_file_name(shard));
+        if let Err(error) = std::fs::write(&path, to_npz(&samples)) {
+            return failure(&format!("couldn't write {}: {error}", path.display()), options);
+        }
+        if !options.json {
+            println!("{}: {} samples", path.display(), samples.len());
+        }
+        written.push((path, samples.len()));
+    }
+    let millis = start.elapsed().as_millis();
+    let total: usize = written.iter().map(|(_, count)| count).sum();
+    if options.json {
+        let shards: Vec<String> = written.iter()
+            .map(|(path, count)| format!("{{\"file\":{},\"samples\":{count}}}", json_string(&path.display().to_string())))
+            .collect();
+        println!("{{\"source\":\"{}\",\"seed\":{},\"shards\":[{}],\"samples\":{total},\"nodes\":{nodes},\"millis\":{millis}}}",
+            export_options.source.name(), export_options.seed, shards.join(","));
+    } else {
+        println!("{total} samples in {} shards written in {millis} ms", written.len());
+    }
+    let expected: usize = shard_sizes.iter().enumerate()
+        .filter(|&(shard, _)| only_shard.is_none_or(|only_shard| only_shard == shard))
+        .map(|(_, count)| count)
+        .sum();
+    if total < expected {
+        return failure(&format!("only {total} of {expected} distinct samples were found in the ply range"), options);
+    }
+    EXIT_OK
+}
+
+fn tablebase_build(args: &[&str], options: &Options) -> i32 {
+    let (path, flags) = match parse_flags(args, &["empty", "random", "seed"]) {
+        Ok(parsed) => parsed,
+        Err(message) => return usage_error(&message),
+    };
+    let Some(path) = path else {
+        return usage_error("tablebase build expects an output file");
+    };
+    let mut max_empty = 8;
+    let mut random = 1000;
+    let mut seed = 0;
+    for (flag, value) in flags {
+        match (flag, value.parse::<u64>()) {
+            ("empty", Ok(value)) if value <= 41 => max_empty = value as i8,
+            ("random", Ok(value)) => random = value as usize,
+            ("seed", Ok(value)) => seed = value,
+            _ => return usage_error(&format!("--{flag} expects a number")),
+        }
+    }
+
+    let start = Instant::now();
+    let tablebase = Tablebase::generate(&random_roots(random, max_empty, seed), max_empty);
+    if let Err(error) = std::fs::write(path, tablebase.to_bytes()) {
+        return failure(&format!("couldn't write {path}: {error}"), options);
+    }
+    let millis = start.elapsed().as_millis();
+    if options.json {
+        println!("{{\"file\":{},\"max_empty\":{max_empty},\"positions\":{},\"millis\":{millis}}}",
+            json_string(path), tablebase.len());
+    } else {
+        println!("{} positions with at most {max_empty} empty squares written to {path} in {millis} ms", tablebase.len());
+    }
+    EXIT_OK
+}
+
+fn self_play(args: &[&str], options: &Options) -> i32 {
+    let (extra, flags) = match parse_flags(args, &["a", "b", "openings", "random", "plies", "balance", "seed", "export"]) {
+        Ok(parsed) => parsed,
+        Err(message) => return usage_error(&message),
+    };
+    if let Some(extra) = extra {
+        return usage_error(&format!("unexpected argument {extra}"));
+    }
+    let mut configs = [EngineConfig::new("a", options.tt_bits), EngineConfig::new("b", options.tt_bits)];
+    let mut openings_path = None;
+    let mut export_path = None;
+    let mut random = 10;
+    let mut plies = 8;
+    let mut balance = 2;
+    let mut seed = 0;
+    for (flag, value) in flags {
+        let parsed = match flag {
+            "a" => configs[0].apply(value),
+            "b" => configs[1].apply(value),
+            "openings" => {
+                openings_path = Some(value);
+                Ok(())
+            }
+            "export" => {
+                export_path = Some(value);
+                Ok(())
+            }
+            "random" => value.parse().map(|value| random = value).map_err(|_| "--random expects a number".to_string()),
+            "plies" => value.parse().ok().filter(|plies| (0..42).contains(plies)).map(|value| plies = value)
+                .ok_or_else(|| "--plies expects a number from 0 to 41".to_string()),
+            "balance" => value.parse().ok().filter(|balance| (0..=21).contains(balance)).map(|value| balance = value)
+                .ok_or_else(|| "--balance expects a score from 0 to 21".to_string()),
+            _ => value.parse().map(|value| seed = value).map_err(|_| "--seed expects a number".to_string()),
+        };
+        if let Err(message) = parsed {
+            return usage_error(&message);
+        }
+    }
+
+    let book = match load_book(options) {
+        Ok(book) => book,
+        Err(code) => return code,
+    };
+    let openings = match openings_path {
+        Some(path) => match read_openings(Path::new(path)) {
+            Ok(openings) => openings,
+            Err(message) => return input_error(&message, options),
+        },
+        None => {
+            let mut table = TranspositionTable::new(options.tt_bits);
+            let openings = random_openings(random, plies, balance, seed, &mut table, &book, &mut 0);
+            if openings.len() < random {
+                return failure(&format!("only {} openings scored from -{balance} to {balance}", openings.len()), options);
+            }
+            openings
+        }
+    };
+
+    let summary = match play_match(configs, &openings, seed, &book) {
+        Ok(summary) => summary,
+        Err(message) => return failure(&message, options),
+    };
+
+    if let Some(path) = export_path {
+        let names = [&summary.players[0].config.name, &summary.players[1].config.name];
+        let lines: Vec<String> = summary.games.iter().map(|game| {
+            let (player1, player2) = if game.first_engine_starts {(names[0], names[1])} else {(names[1], names[0])};
+            let result = match (game.result, game.first_engine_starts) {
+                (MatchResult::Draw, _) => "1/2-1/2",
+                (MatchResult::FirstEngineWin, true) | (MatchResult::SecondEngineWin, false) => "1-0",
+                _ => "0-1",
+            };
+            format!("{} {player1} {player2} {result}\n", game.moves)
+        }).collect();
+        if let Err(error) = std::fs::write(path, lines.concat()) {
+            return failure(&format!("couldn't write {path}: {error}"), options);
+        }
+    }
+
+    if options.json {
+        let players: Vec<String> = summary.players.iter().map(|player| {
+            format!("{{\"name\":{},\"moves\":{},\"nodes\":{},\"micros\":{}}}", json_string(&player.config.name),
+                player.moves, player.nodes, player.time.as_micros())
+        }).collect();
+        println!("{{\"games\":{},\"a_wins\":{},\"b_wins\":{},\"draws\":{},\"players\":[{}]}}", summary.games.len(),
+            summary.count(MatchResult::FirstEngineWin), summary.count(MatchResult::SecondEngineWin),
+            summary.count(MatchResult::Draw), players.join(","));
+    } else {
+        summary.print_table();
+    }
+    EXIT_OK
+}
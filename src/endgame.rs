@@ -0,0 +1,57 @@
+use crate::game::*;
+use crate::tablebase::Tablebase;
+use std::cmp::max;
+
+// Empty squares at or below which negamax hands over to solve_endgame. Tuned with `suite --endgame` on the
+// fixtures: 12 took the Test_L3 and Test_L2 sets from 42us and 227us a position to 3us and 145us,
+// while larger values started to cost time on Test_L1.
+pub const ENDGAME_EMPTY_SQUARES: i8 = 12;
+
+// Exhaustive alpha-beta over raw bitboards for nearly full boards. There are too few positions left
+// for transposition table probes to pay for themselves, so none are made. `tablebase` is probed at its
+// roots' depth, like negamax does when it hands over below that depth.
+// `player_squares` are the stones of the side to move and the result is from their point of view.
+pub fn solve_endgame(player_squares: u64, played: u64, alpha: i8, beta: i8, tablebase: &Tablebase, nodes: &mut u64)->i8{
+    *nodes += 1;
+    let moves_made = played.count_ones() as i8;
+    if moves_made == 42 {
+        return 0;
+    }
+
+    let playable = get_playable_squares(played);
+    if get_winning_squares(player_squares, played) & playable != 0 {
+        return 21 - moves_made/2;
+    }
+    if 42 - moves_made == tablebase.max_empty {
+        if let Some(score) = tablebase.probe(player_squares, played) {
+            return score;
+        }
+    }
+    let opponent_squares = played & !player_squares;
+    let non_losing = get_non_losing_squares(opponent_squares, played);
+    if non_losing == 0 {
+        return -21 + (moves_made+1)/2;
+    }
+
+    // Neither side wins with its next stone, and a full board is at worst a draw
+    let min_possible = (-21 + (moves_made+3)/2).min(0);
+    let max_possible = (20 - moves_made/2).max(0);
+    let mut alpha = max(alpha, min_possible);
+    let beta = beta.min(max_possible);
+    if alpha >= beta {
+        return alpha;
+    }
+
+    for col_num in MOVE_ORDER {
+        let square = non_losing & (COLUMN_MASK << (8 * col_num));
+        if square == 0 {
+            continue;
+        }
+        let value = -solve_endgame(opponent_squares, played | square, -beta, -alpha, tablebase, nodes);
+        if value >= beta {
+            return value;
+        }
+        alpha = max(alpha, value);
+    }
+    alpha
+}
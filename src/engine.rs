@@ -1,11 +1,14 @@
 use crate::game::*;
 use crate::book::*;
-use std::collections::HashSet;
+use crate::ordering::*;
+use crate::endgame::*;
 use std::u64;
 use std::{cmp::{max, min}, i8};
 
-pub fn negamax(game:&mut Game, alpha: i8, beta: i8, transposition_table: &mut TranspositionTable,
-        book: &OpeningBook, nodes: &mut u64)->i8{
+// Positions with `endgame_empty` or fewer empty squares are handed to solve_endgame
+#[allow(clippy::too_many_arguments)]
+pub fn negamax<O: MoveOrderer>(game:&mut Game, alpha: i8, beta: i8, transposition_table: &mut TranspositionTable,
+        book: &OpeningBook, orderer: &mut O, endgame_empty: i8, nodes: &mut u64)->i8{
     *nodes += 1;
 
     match &game.game_status {
@@ -31,38 +34,50 @@ pub fn negamax(game:&mut Game, alpha: i8, beta: i8, transposition_table: &mut Tr
         return max_possible;
     }
 
-    if game.moves_made == 12 {
-        if let Some(eval) = book.lookup(game.board_set, game.board_p1){
-            return eval;
+    if book.covers(game.moves_made) {
+        if let Some(hit) = book.lookup_position(game.board_set, game.board_p1){
+            return hit.eval;
         }
     }
 
-    let opponent_slots = if game.player_one_turn{!game.board_p1 & game.board_set} else {game.board_p1 & game.board_set};
-    let opponent_winning_squares = get_winning_squares(opponent_slots, game.board_set);
+    // Tables are built down from roots with max_empty empty squares, so a position below that depth is only
+    // covered when its ancestor at the roots' depth was, and that ancestor would have been found first.
+    // solve_endgame makes the same probe when the handoff comes above the roots' depth.
+    if 42 - game.moves_made == book.tablebase.max_empty {
+        if let Some(score) = book.tablebase.probe(player_slots, game.board_set) {
+            return score;
+        }
+    }
 
-    match (board_playable & opponent_winning_squares).count_ones() {
-        0 => (),
-        1 => {
-            for i in 0..COLS{
-                if board_playable & opponent_winning_squares & (COLUMN_MASK << (i * 8)) != 0{
-                    if let (true, row_number) = game.make_move(i){
-                        let val = -negamax(game, -beta, -alpha, transposition_table, book, nodes);
-                        game.unmake_move(i, row_number);
-                        return val;
-                    }
-                }
-            }
-        },
-        _ => return min_possible
+    if 42 - game.moves_made <= endgame_empty {
+        return solve_endgame(player_slots, game.board_set, alpha, beta, &book.tablebase, nodes);
     }
 
+    let non_losing_moves = game.possible_non_losing_moves();
+    if non_losing_moves == 0 {
+        return min_possible;
+    }
+    // Only one move doesn't lose at once, so the position is worth whatever that move is worth
+    if non_losing_moves.count_ones() == 1 {
+        let col_num = (non_losing_moves.trailing_zeros() / 8) as u8;
+        let (_, row_number) = game.make_move(col_num);
+        let val = -negamax(game, -beta, -alpha, transposition_table, book, orderer, endgame_empty, nodes);
+        game.unmake_move(col_num, row_number);
+        return val;
+    }
+    // The opponent can't win with their next stone, and a full board is at worst a draw
+    let min_possible = (min_possible + 1).min(0);
+    if min_possible >= beta {
+        return min_possible;
+    }
 
     let mut alpha = alpha;
     let mut beta = beta;
     let pos = game.get_hash();
-    
+    let mut tt_move = None;
     
     if let Some(eval) = transposition_table.get(pos){
+        tt_move = eval.best_move;
         match eval.value_type {
             ValueType::Exact => {
                 return eval.value;
@@ -88,19 +103,29 @@ pub fn negamax(game:&mut Game, alpha: i8, beta: i8, transposition_table: &mut Tr
 
     
     let mut value = i8::MIN;
-    let move_order = game.get_candidate_moves();
+    let mut best_move = None;
+    let move_order = orderer.order_moves(game, tt_move);
     for col_num in move_order {
         if col_num == 255{
             break;
         }
+        if non_losing_moves & (COLUMN_MASK << (8 * col_num)) == 0 {
+            continue;
+        }
         if let (true, row_number) = game.make_move(col_num){
-            value = max(value, -negamax(game, -beta, -alpha, transposition_table, book, nodes));
+            let col_value = -negamax(game, -beta, -alpha, transposition_table, book, orderer, endgame_empty, nodes);
             game.unmake_move(col_num, row_number);
+            if col_value > value {
+                value = col_value;
+                best_move = Some(col_num);
+            }
             alpha = max(alpha, value);
             if alpha >= beta {
+                orderer.record_cutoff(game, col_num);
                 transposition_table.insert(pos, Eval {
                     value: beta,
                     value_type: ValueType::LowerBound,
+                    best_move,
                 });
                 return beta;
             }
@@ -108,7 +133,8 @@ pub fn negamax(game:&mut Game, alpha: i8, beta: i8, transposition_table: &mut Tr
     }
     transposition_table.insert(pos, Eval {
         value: alpha,
-        value_type: ValueType::UpperBound
+        value_type: ValueType::UpperBound,
+        best_move,
     });
     alpha
 }
@@ -117,6 +143,8 @@ pub fn negamax(game:&mut Game, alpha: i8, beta: i8, transposition_table: &mut Tr
 pub struct Eval{
     value: i8,
     value_type: ValueType,
+    // Move that produced the value. For a lower bound this is the move that caused the cutoff.
+    best_move: Option<u8>,
 }
 
 #[derive(PartialEq, Eq, Clone)]
@@ -126,13 +154,19 @@ pub enum ValueType {
     LowerBound,
 }
 
+const NO_MOVE: u8 = 7;
+
+// Entries keep the key from bit 11 up, so smaller tables would confuse positions sharing a slot
+pub const MIN_TT_BITS: usize = 11;
+
 pub struct TranspositionTable{
     address_mask : u64,
     entries : Box<[u64]>,
 }
 
 impl TranspositionTable {
-    // 64 bit entries. 56 bits for key. Last 8 bits for value. Eval +50 for upper bound -50 for lower bound.
+    // 64 bit entries. 53 bits for key. 3 bits for best move (7 when unknown). Last 8 bits for value.
+    // Eval +50 for upper bound -50 for lower bound.
     pub fn new(n: usize) -> Self {
         Self {
             address_mask: (1<<n)-1,
@@ -146,7 +180,8 @@ impl TranspositionTable {
             ValueType::UpperBound => value.value + 50,
             ValueType::Exact => value.value,
         };
-        let entry = key >> 8 << 8 | (entry_val as u8 as u64);
+        let entry_move = value.best_move.unwrap_or(NO_MOVE) as u64;
+        let entry = key >> 11 << 11 | entry_move << 8 | (entry_val as u8 as u64);
         self.entries[position as usize] = entry;
     }
     pub fn get(&mut self, key: u64)->Option<Eval>{
@@ -155,23 +190,30 @@ impl TranspositionTable {
         }
         let position = key & self.address_mask;
         let entry =  self.entries[position as usize];
-        if key >> 8 == entry >> 8 {
+        if key >> 11 == entry >> 11 {
             let entry_val = entry as i8;
+            let best_move = match (entry >> 8) as u8 & NO_MOVE {
+                NO_MOVE => None,
+                col_num => Some(col_num),
+            };
             if entry_val < -25 {
                 return Some(Eval{
                     value: entry_val + 50,
                     value_type: ValueType::LowerBound,
+                    best_move,
                 });
             }
             if entry_val > 25 {
                 return Some(Eval{
                     value: entry_val - 50,
                     value_type: ValueType::UpperBound,
+                    best_move,
                 });
             }
             return Some(Eval{
                 value: entry_val,
                 value_type: ValueType::Exact,
+                best_move,
             });
         }
         None
@@ -179,10 +221,45 @@ impl TranspositionTable {
 }
 
 pub fn search(game: &mut Game, transposition_table: &mut TranspositionTable, book: &OpeningBook, nodes: &mut u64)->i8{
+    // Table move first searched the fewest nodes on the fixture sets, ahead of plain threat ordering.
+    // History and killer orderings lose the threat counts and search several times more.
+    search_with(game, transposition_table, book, &mut TableMoveOrdering, ENDGAME_EMPTY_SQUARES, nodes)
+}
+
+// Runs search with a move ordering and endgame threshold chosen at runtime, e.g. to compare node counts
+pub fn search_ordered(game: &mut Game, transposition_table: &mut TranspositionTable, book: &OpeningBook,
+        ordering: MoveOrdering, endgame_empty: i8, nodes: &mut u64)->i8{
+    match ordering {
+        MoveOrdering::Threats => search_with(game, transposition_table, book, &mut ThreatOrdering, endgame_empty, nodes),
+        MoveOrdering::Centre => search_with(game, transposition_table, book, &mut CentreOrdering, endgame_empty, nodes),
+        MoveOrdering::History => search_with(game, transposition_table, book, &mut HistoryOrdering::default(), endgame_empty, nodes),
+        MoveOrdering::Killers => search_with(game, transposition_table, book, &mut KillerOrdering::default(), endgame_empty, nodes),
+        MoveOrdering::TableMove => search_with(game, transposition_table, book, &mut TableMoveOrdering, endgame_empty, nodes),
+    }
+}
+
+pub fn search_with<O: MoveOrderer>(game: &mut Game, transposition_table: &mut TranspositionTable, book: &OpeningBook,
+        orderer: &mut O, endgame_empty: i8, nodes: &mut u64)->i8{
     // Return early if game is already over
     if game.game_status == GameStatus::Player1Win || game.game_status == GameStatus::Player2Win {
         return -22 + (game.moves_made+1)/2
     }
+    // Positions in the book need no search
+    if game.game_status == GameStatus::InProgress && book.covers(game.moves_made) {
+        if let Some(hit) = book.lookup_position(game.board_set, game.board_p1) {
+            *nodes += 1;
+            return hit.eval;
+        }
+    }
+
+    // Negamax only probes the tablebase at the roots' depth, so positions deeper in the table are looked up here
+    if game.game_status == GameStatus::InProgress {
+        let player_slots = if game.player_one_turn{game.board_p1 & game.board_set} else {!game.board_p1 & game.board_set};
+        if let Some(score) = book.tablebase.probe(player_slots, game.board_set) {
+            *nodes += 1;
+            return score;
+        }
+    }
 
     let mut maximum_possible = 21 - game.moves_made/2;
     let mut minimum_possible = -21 + (game.moves_made+1)/2;
@@ -202,7 +279,7 @@ pub fn search(game: &mut Game, transposition_table: &mut TranspositionTable, boo
             window = min(window, minimum_possible/2);
         }
         //println!("{minimum_possible}, {maximum_possible}, {window}");
-        let result = negamax(game, window, window+1, transposition_table, book, nodes);
+        let result = negamax(game, window, window+1, transposition_table, book, orderer, endgame_empty, nodes);
         //println!("{minimum_possible}, {maximum_possible}, {window}, {result}");
         if result <= window {
             maximum_possible = window
@@ -213,29 +290,169 @@ pub fn search(game: &mut Game, transposition_table: &mut TranspositionTable, boo
     minimum_possible
 }
 
-pub fn calculate_tree_width(game:&mut Game, plies: i8, seen: &mut HashSet<u64>)->u64{
-    if game.moves_made == plies {
-        1
-    } else {
-        let mut positions = 0;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TieBreak {
+    // Use the move stored in the transposition table when it is proven optimal, otherwise fall back to CentreFirst
+    TableMove,
+    CentreFirst,
+    LeftFirst,
+}
+
+impl TieBreak {
+    pub const ALL: [TieBreak; 3] = [TieBreak::TableMove, TieBreak::CentreFirst, TieBreak::LeftFirst];
 
-        for col_number in 0..COLS{
-            if let (true, row_number) = game.make_move(col_number){
-                let pos = game.get_hash();
-                if seen.contains(&pos){
-                    game.unmake_move(col_number, row_number);
-                    continue;
-                }
-                else {
-                    seen.insert(pos);
-                    print!("{col_number}");
-                    positions += calculate_tree_width(game, plies, seen);
-                    game.unmake_move(col_number, row_number);
-                    print!("\x08 \x08");
-                }
+    pub fn name(&self) -> &'static str {
+        match self {
+            TieBreak::TableMove => "tt",
+            TieBreak::CentreFirst => "centre",
+            TieBreak::LeftFirst => "left",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|tie_break| tie_break.name() == name)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct PvOptions {
+    pub tie_break: TieBreak,
+    // When false any move that keeps the theoretical result (win, draw or loss) is accepted, not only the fastest
+    pub fastest_win: bool,
+}
+
+impl Default for PvOptions {
+    fn default() -> Self {
+        Self {
+            tie_break: TieBreak::TableMove,
+            fastest_win: true,
+        }
+    }
+}
 
+pub fn principal_variation(game: &mut Game, transposition_table: &mut TranspositionTable, book: &OpeningBook,
+        nodes: &mut u64)->Vec<u8>{
+    principal_variation_with(game, transposition_table, book, nodes, PvOptions::default())
+}
+
+// Plays out optimal moves until the game ends and returns them. The game is restored before returning.
+pub fn principal_variation_with(game: &mut Game, transposition_table: &mut TranspositionTable, book: &OpeningBook,
+        nodes: &mut u64, options: PvOptions)->Vec<u8>{
+    let mut line = Vec::new();
+    let mut played = Vec::new();
+    let mut score = search(game, transposition_table, book, nodes);
+
+    while game.game_status == GameStatus::InProgress {
+        let (col_num, col_score) = pv_move(game, transposition_table, book, nodes, score, options);
+        let (_, row_number) = game.make_move(col_num);
+        line.push(col_num);
+        played.push((col_num, row_number));
+        score = -col_score;
+    }
+    for (col_num, row_number) in played.into_iter().rev(){
+        game.unmake_move(col_num, row_number);
+    }
+    line
+}
+
+fn pv_move(game: &mut Game, transposition_table: &mut TranspositionTable, book: &OpeningBook,
+        nodes: &mut u64, score: i8, options: PvOptions)->(u8, i8){
+    if options.tie_break == TieBreak::TableMove && options.fastest_win {
+        // A stored cutoff at or above the exact score proves the move is optimal without searching the children
+        if let Some(Eval {value, value_type: ValueType::LowerBound, best_move: Some(col_num)}) = transposition_table.get(game.get_hash()) {
+            if value >= score && game.get_board_playable() & (COLUMN_MASK << (8 * col_num)) != 0 {
+                return (col_num, score);
+            }
+        }
+    }
+
+    let col_order = match options.tie_break {
+        TieBreak::LeftFirst => [0, 1, 2, 3, 4, 5, 6],
+        _ => MOVE_ORDER,
+    };
+    let mut best: Option<(u8, i8)> = None;
+    for col_num in col_order {
+        if let (true, row_number) = game.make_move(col_num){
+            let eval = -search(game, transposition_table, book, nodes);
+            game.unmake_move(col_num, row_number);
+            let accepted = if options.fastest_win {eval == score} else {eval.signum() == score.signum()};
+            if accepted {
+                return (col_num, eval);
+            }
+            if best.is_none_or(|(_, best_eval)| eval > best_eval){
+                best = Some((col_num, eval));
+            }
+        }
+    }
+    // Only reached if the book disagrees with the search by a distance point
+    best.expect("pv_move called on a finished game")
+}
+
+pub struct MoveExplanation {
+    pub col_num: u8,
+    // Score from the point of view of the player making this move
+    pub score: i8,
+    // The opponent's best reply, None if this move ends the game
+    pub refutation: Option<u8>,
+    // Best play following this move, starting with the refutation
+    pub line: Vec<u8>,
+}
+
+impl MoveExplanation {
+    pub fn to_json(&self) -> String {
+        let refutation = match self.refutation {
+            Some(col_num) => col_num.to_string(),
+            None => "null".to_string(),
+        };
+        let line: Vec<String> = self.line.iter().map(|col_num| col_num.to_string()).collect();
+        format!("{{\"column\":{},\"score\":{},\"refutation\":{},\"line\":[{}]}}",
+            self.col_num, self.score, refutation, line.join(","))
+    }
+}
+
+// Score of every column from the point of view of the player to move, None where the column can't be played
+pub fn column_scores(game: &mut Game, transposition_table: &mut TranspositionTable, book: &OpeningBook, nodes: &mut u64)->[Option<i8>; 7]{
+    let mut scores = [None; 7];
+    for col_num in 0..COLS{
+        if let (true, row_number) = game.make_move(col_num){
+            scores[col_num as usize] = Some(-search(game, transposition_table, book, nodes));
+            game.unmake_move(col_num, row_number);
+        }
+    }
+    scores
+}
+
+// Scores every legal move and returns the column with the best score for the player to move
+pub fn best_move(game: &mut Game, transposition_table: &mut TranspositionTable, book: &OpeningBook, nodes: &mut u64)->Option<(u8, i8)>{
+    let mut best: Option<(u8, i8)> = None;
+    for (col_num, eval) in column_scores(game, transposition_table, book, nodes).into_iter().enumerate(){
+        if let Some(eval) = eval {
+            if best.is_none_or(|(_, best_eval)| eval > best_eval){
+                best = Some((col_num as u8, eval));
             }
         }
-        positions
     }
-}
\ No newline at end of file
+    best
+}
+
+// For every legal column give its score, the opponent's refutation and up to max_line plies of best play after it
+pub fn explain_moves(game: &mut Game, transposition_table: &mut TranspositionTable, book: &OpeningBook,
+        nodes: &mut u64, max_line: usize)->Vec<MoveExplanation>{
+    let mut explanations = Vec::new();
+    for col_num in 0..COLS{
+        if let (true, row_number) = game.make_move(col_num){
+            let score = -search(game, transposition_table, book, nodes);
+            let mut line = principal_variation(game, transposition_table, book, nodes);
+            line.truncate(max_line);
+            game.unmake_move(col_num, row_number);
+
+            explanations.push(MoveExplanation {
+                col_num,
+                score,
+                refutation: line.first().copied(),
+                line,
+            });
+        }
+    }
+    explanations
+}
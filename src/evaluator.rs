@@ -0,0 +1,134 @@
+use crate::game::*;
+
+// Estimates positions where a depth-limited search stops. Scores are for the side to move on the solver's scale,
+// so a trained network predicting solver scores plugs straight in. The search calls make_move and unmake_move
+// alongside Game's, letting an evaluator update its state incrementally rather than from scratch.
+pub trait Evaluator {
+    // Brings the evaluator in line with the position, e.g. at the root of a search
+    fn reset(&mut self, game: &Game);
+
+    // Called with the position after col_num was played at row_number
+    fn make_move(&mut self, game: &Game, _col_num: u8, _row_number: u8) {
+        self.reset(game);
+    }
+
+    // Called with the position after the stone at col_num, row_number was taken back
+    fn unmake_move(&mut self, game: &Game, _col_num: u8, _row_number: u8) {
+        self.reset(game);
+    }
+
+    fn evaluate(&mut self, game: &Game) -> f32;
+}
+
+// Squares that would complete four for the side to move less those for the opponent. Needs no state.
+#[derive(Default)]
+pub struct ThreatEvaluator;
+
+impl Evaluator for ThreatEvaluator {
+    fn reset(&mut self, _game: &Game) {}
+
+    fn evaluate(&mut self, game: &Game) -> f32 {
+        let p1_squares = game.board_set & game.board_p1;
+        let (own, opponent) = if game.player_one_turn {
+            (p1_squares, game.board_set & !p1_squares)
+        } else {
+            (game.board_set & !p1_squares, p1_squares)
+        };
+        let threats = |squares| get_winning_squares(squares, game.board_set).count_ones() as f32;
+        threats(own) - threats(opponent)
+    }
+}
+
+fn play<E: Evaluator + ?Sized>(game: &mut Game, evaluator: &mut E, col_num: u8) -> Option<u8> {
+    let (true, row_number) = game.make_move(col_num) else { return None };
+    evaluator.make_move(game, col_num, row_number);
+    Some(row_number)
+}
+
+fn take_back<E: Evaluator + ?Sized>(game: &mut Game, evaluator: &mut E, col_num: u8, row_number: u8) {
+    game.unmake_move(col_num, row_number);
+    evaluator.unmake_move(game, col_num, row_number);
+}
+
+// Alpha-beta to `depth` plies. Wins, losses and draws found within the horizon get the solver's exact scores and
+// the evaluator's estimates are clamped to the scores still possible, so a deep enough search matches the solver.
+fn negamax<E: Evaluator + ?Sized>(game: &mut Game, evaluator: &mut E, depth: u8, mut alpha: f32, beta: f32,
+        nodes: &mut u64) -> f32 {
+    *nodes += 1;
+    match game.game_status {
+        GameStatus::InProgress => (),
+        GameStatus::Draw => return 0.0,
+        // Only reached by the player who lost
+        _ => return (-22 + (game.moves_made + 1)/2) as f32,
+    }
+    let max_possible = (21 - game.moves_made/2) as f32;
+    if game.get_winning_move().is_some() {
+        return max_possible;
+    }
+    let min_possible = (-21 + (game.moves_made + 1)/2) as f32;
+    let non_losing_moves = game.possible_non_losing_moves();
+    if non_losing_moves == 0 {
+        return min_possible;
+    }
+    if depth == 0 {
+        return evaluator.evaluate(game).clamp(min_possible, max_possible);
+    }
+
+    let mut best = f32::NEG_INFINITY;
+    for col_num in MOVE_ORDER {
+        if non_losing_moves & (COLUMN_MASK << (8 * col_num)) == 0 {
+            continue;
+        }
+        if let Some(row_number) = play(game, evaluator, col_num) {
+            let score = -negamax(game, evaluator, depth - 1, -beta, -alpha, nodes);
+            take_back(game, evaluator, col_num, row_number);
+            best = best.max(score);
+            alpha = alpha.max(best);
+            if alpha >= beta {
+                break;
+            }
+        }
+    }
+    best
+}
+
+// Heuristic score of the position for the side to move, searching `depth` plies before asking the evaluator
+#[cfg(test)]
+pub fn heuristic_search<E: Evaluator + ?Sized>(game: &mut Game, evaluator: &mut E, depth: u8, nodes: &mut u64) -> f32 {
+    evaluator.reset(game);
+    negamax(game, evaluator, depth, f32::NEG_INFINITY, f32::INFINITY, nodes)
+}
+
+// Heuristic score of every column for the side to move, None where the column can't be played. Each column
+// is searched `depth` plies deep, its own move included.
+pub fn heuristic_column_scores<E: Evaluator + ?Sized>(game: &mut Game, evaluator: &mut E, depth: u8,
+        nodes: &mut u64) -> [Option<f32>; 7] {
+    evaluator.reset(game);
+    let mut scores = [None; 7];
+    if game.game_status != GameStatus::InProgress {
+        return scores;
+    }
+    for col_num in 0..COLS {
+        if let Some(row_number) = play(game, evaluator, col_num) {
+            let score = -negamax(game, evaluator, depth.saturating_sub(1), f32::NEG_INFINITY, f32::INFINITY, nodes);
+            take_back(game, evaluator, col_num, row_number);
+            scores[col_num as usize] = Some(score);
+        }
+    }
+    scores
+}
+
+// The column with the best heuristic score, the centremost among equals
+pub fn heuristic_best_move<E: Evaluator + ?Sized>(game: &mut Game, evaluator: &mut E, depth: u8,
+        nodes: &mut u64) -> Option<(u8, f32)> {
+    let scores = heuristic_column_scores(game, evaluator, depth, nodes);
+    let mut best: Option<(u8, f32)> = None;
+    for col_num in MOVE_ORDER {
+        if let Some(score) = scores[col_num as usize] {
+            if best.is_none_or(|(_, best_score)| score > best_score) {
+                best = Some((col_num, score));
+            }
+        }
+    }
+    best
+}
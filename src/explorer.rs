@@ -0,0 +1,170 @@
+use crate::game::*;
+use crate::engine::*;
+use crate::book::*;
+use std::collections::HashMap;
+
+// Theoretical value of every continuation of a position before BOOK_PLY, found by negamax over the tree
+// down to BOOK_PLY with the book scoring its leaves. Leaves missing from the book are searched live.
+#[derive(Default)]
+pub struct OpeningExplorer {
+    // Bounds proven for positions in the tree, keyed by their ply and the smaller huffman code of the
+    // position and its mirror image, which is unique at a given ply
+    bounds: HashMap<u64, Bounds>,
+    explored: HashMap<u64, Exploration>,
+}
+
+#[derive(Clone, Copy)]
+struct Bounds {
+    lower: i8,
+    upper: i8,
+    // Some leaf the bounds rest on was searched live
+    searched: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Continuation {
+    pub col_num: u8,
+    // Score from the point of view of the player making this move
+    pub score: i8,
+    // Whether the score rests on a leaf that wasn't in the book
+    pub searched: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Exploration {
+    // Every legal column, in column order
+    pub continuations: Vec<Continuation>,
+    // Huffman codes of the BOOK_PLY positions searched live while answering, in code order. Subtrees whose
+    // bounds were already cached only show up through Continuation::searched.
+    pub fallbacks: Vec<i32>,
+}
+
+impl Exploration {
+    pub fn to_json(&self) -> String {
+        let continuations: Vec<String> = self.continuations.iter()
+            .map(|continuation| format!("{{\"column\":{},\"score\":{},\"searched\":{}}}",
+                continuation.col_num, continuation.score, continuation.searched))
+            .collect();
+        let fallbacks: Vec<String> = self.fallbacks.iter().map(|code| code.to_string()).collect();
+        format!("{{\"continuations\":[{}],\"fallbacks\":[{}]}}", continuations.join(","), fallbacks.join(","))
+    }
+}
+
+fn tree_key(game: &Game) -> u64 {
+    let code = huffman_code(game.board_set, game.board_p1, false).min(huffman_code(game.board_set, game.board_p1, true));
+    (game.moves_made as u64) << 32 | code as u32 as u64
+}
+
+impl OpeningExplorer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Positions held in the explorer's caches
+    #[cfg(test)]
+    pub fn cached(&self) -> usize {
+        self.bounds.len() + self.explored.len()
+    }
+
+    // None unless the game is in progress before BOOK_PLY. The game is restored before returning.
+    pub fn explore(&mut self, game: &mut Game, transposition_table: &mut TranspositionTable, book: &OpeningBook,
+            nodes: &mut u64) -> Option<Exploration> {
+        if game.game_status != GameStatus::InProgress || game.moves_made >= BOOK_PLY {
+            return None;
+        }
+        // Not the mirrored key, the columns would come back mirrored
+        let key = (game.moves_made as u64) << 32 | huffman_code(game.board_set, game.board_p1, false) as u32 as u64;
+        if let Some(exploration) = self.explored.get(&key) {
+            return Some(exploration.clone());
+        }
+
+        let mut exploration = Exploration::default();
+        for col_num in 0..COLS {
+            if let (true, row_number) = game.make_move(col_num) {
+                let continuation = if game.game_status == GameStatus::InProgress {
+                    let (score, searched) = self.negamax(game, -ABOVE_MAX, ABOVE_MAX, transposition_table, book,
+                        &mut exploration.fallbacks, nodes);
+                    Continuation {col_num, score: -score, searched}
+                } else {
+                    // A win, draws can't happen this early
+                    Continuation {col_num, score: 21 - (game.moves_made - 1)/2, searched: false}
+                };
+                game.unmake_move(col_num, row_number);
+                exploration.continuations.push(continuation);
+            }
+        }
+        exploration.fallbacks.sort_unstable();
+        exploration.fallbacks.dedup();
+        self.explored.insert(key, exploration.clone());
+        Some(exploration)
+    }
+
+    // Fail soft alpha-beta returning the score for the side to move and whether a live search was involved
+    #[allow(clippy::too_many_arguments)]
+    fn negamax(&mut self, game: &mut Game, mut alpha: i8, mut beta: i8, transposition_table: &mut TranspositionTable,
+            book: &OpeningBook, fallbacks: &mut Vec<i32>, nodes: &mut u64) -> (i8, bool) {
+        *nodes += 1;
+        if game.get_winning_move().is_some() {
+            return (21 - game.moves_made/2, false);
+        }
+        if game.moves_made == BOOK_PLY {
+            return self.leaf(game, transposition_table, book, fallbacks, nodes);
+        }
+
+        let key = tree_key(game);
+        let mut bounds = self.bounds.get(&key).copied()
+            .unwrap_or(Bounds {lower: -ABOVE_MAX, upper: ABOVE_MAX, searched: false});
+        if bounds.lower >= beta || bounds.lower == bounds.upper {
+            return (bounds.lower, bounds.searched);
+        }
+        if bounds.upper <= alpha {
+            return (bounds.upper, bounds.searched);
+        }
+        alpha = alpha.max(bounds.lower);
+        beta = beta.min(bounds.upper);
+
+        let alpha_start = alpha;
+        let mut best = -ABOVE_MAX;
+        let mut searched = false;
+        for col_num in MOVE_ORDER {
+            if let (true, row_number) = game.make_move(col_num) {
+                let (score, child_searched) = self.negamax(game, -beta, -alpha, transposition_table, book, fallbacks, nodes);
+                game.unmake_move(col_num, row_number);
+                searched |= child_searched;
+                best = best.max(-score);
+                alpha = alpha.max(best);
+                if alpha >= beta {
+                    break;
+                }
+            }
+        }
+
+        if best > alpha_start {
+            bounds.lower = best;
+        }
+        if best < beta {
+            bounds.upper = best;
+        }
+        bounds.searched |= searched;
+        self.bounds.insert(key, bounds);
+        (best, bounds.searched)
+    }
+
+    fn leaf(&mut self, game: &mut Game, transposition_table: &mut TranspositionTable, book: &OpeningBook,
+            fallbacks: &mut Vec<i32>, nodes: &mut u64) -> (i8, bool) {
+        if let Some(entry) = book.lookup_entry(game.board_set, game.board_p1) {
+            return (entry.eval, false);
+        }
+        let key = tree_key(game);
+        fallbacks.push(huffman_code(game.board_set, game.board_p1, false));
+        if let Some(bounds) = self.bounds.get(&key) {
+            return (bounds.lower, true);
+        }
+        let score = search(game, transposition_table, book, nodes);
+        self.bounds.insert(key, Bounds {lower: score, upper: score, searched: true});
+        (score, true)
+    }
+}
+
+// Outside every score, so bounds starting here are open
+const ABOVE_MAX: i8 = 22;
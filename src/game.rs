@@ -9,7 +9,7 @@ pub enum Slot {
     Player1,
     Player2,
 }
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum GameStatus {
     InProgress,
     Draw,
@@ -87,6 +87,15 @@ pub fn set_bit(board: u64, column_number:u8, row_number:u8, set: bool)->u64{
     }
 }
 
+// Reflects a board left to right, column 0 swapping with column 6
+pub fn mirror_board(board: u64)->u64{
+    let mut mirrored = 0;
+    for col_num in 0..COLS {
+        mirrored |= ((board >> (8 * col_num)) & COLUMN_MASK) << (8 * (COLS - 1 - col_num));
+    }
+    mirrored
+}
+
 pub fn print_board(board: u64){
     for y in (0..ROWS).rev() {
         for x in 0..COLS{
@@ -155,6 +164,33 @@ pub fn get_winning_squares(player_squares:u64, played: u64)->u64{
     winning_squares & !played & *BOARD_MASK
 }
 
+// Empty squares with a stone or the floor beneath them
+pub fn get_playable_squares(played: u64)->u64{
+    ((played << 1) | *BOTTOM_ROW) & !played & *BOARD_MASK
+}
+
+// Unique key for a position. Adding the played stones and the bottom row to the side to move's stones leaves
+// a marker bit above each column's top stone with the owners below it, so the key fits in 55 bits.
+pub fn get_position_key(player_squares: u64, played: u64)->u64{
+    player_squares + played + *BOTTOM_ROW
+}
+
+// Playable squares that don't hand the opponent an immediate win. If the opponent has a playable winning
+// square it must be blocked, and a square directly beneath an opponent winning square would let them play it.
+// Empty when every move loses at once.
+pub fn get_non_losing_squares(opponent_squares: u64, played: u64)->u64{
+    let opponent_winning_squares = get_winning_squares(opponent_squares, played);
+    let mut possible = get_playable_squares(played);
+    let forced_moves = possible & opponent_winning_squares;
+    if forced_moves != 0 {
+        if forced_moves.count_ones() > 1 {
+            return 0;
+        }
+        possible = forced_moves;
+    }
+    possible & !(opponent_winning_squares >> 1)
+}
+
 pub fn stable_sort_moves(col_scores: [(usize, i32);7], playable_cols: usize)->[usize;7]{
     let mut move_order = [255;7];
     let mut best_score_index;
@@ -321,7 +357,7 @@ impl Game {
     }
 
     pub fn get_board_playable(&self)->u64{
-        ((self.board_set << 1) | *BOTTOM_ROW) & !(self.board_set) & *BOARD_MASK
+        get_playable_squares(self.board_set)
     }
 
     pub fn get_winning_move(&self)->Option<u8>{
@@ -334,6 +370,11 @@ impl Game {
         None 
     }
 
+    pub fn possible_non_losing_moves(&self)->u64{
+        let opponent_squares = if self.player_one_turn {self.board_set & !self.board_p1} else {self.board_set & self.board_p1};
+        get_non_losing_squares(opponent_squares, self.board_set)
+    }
+
     pub fn get_candidate_moves(&mut self)->[u8;7]{
         // We check for winning moves separately. We get candidate moves by checking for places where we have three tokens in a row
         // that could be extended to four followed by two in a row that can be extended to four
@@ -343,10 +384,19 @@ impl Game {
             self.board_set & !self.board_p1
         };
 
+        // Moves that lose at once are left out unless there is nothing else to play
+        let mut candidate_squares = self.possible_non_losing_moves();
+        if candidate_squares == 0 {
+            candidate_squares = self.get_board_playable();
+        }
+
         // Loop through moves and evaluate their potential of being part of a winning sequence.
         let mut col_scores = [(0,0);7];
         let mut playable_cols = 0;
         for col_number in MOVE_ORDER{
+            if candidate_squares & (COLUMN_MASK << (8 * col_number)) == 0 {
+                continue;
+            }
             if let (true, row_number) = self.make_move(col_number){
                 // The playing player has tried his move so we need the squares of the one whose turn it isn't 
                 let player_squares = if self.player_one_turn {!self.board_p1 & self.board_set} else {self.board_p1 & self.board_set};
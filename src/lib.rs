@@ -1,15 +1,43 @@
 mod game;
 mod engine;
 mod book;
+mod analysis;
+mod random;
+mod perft;
+mod ordering;
+mod endgame;
+mod tablebase;
+mod packed_book;
+mod explorer;
+mod record;
+mod annotate;
+mod puzzle;
+mod positions;
+mod training;
+mod evaluator;
+mod mlp;
+#[cfg(not(target_arch = "wasm32"))]
+mod cli;
+#[cfg(not(target_arch = "wasm32"))]
+mod bench;
+#[cfg(not(target_arch = "wasm32"))]
+mod selfplay;
+#[cfg(test)]
+mod tests;
 
 use game::*;
 use engine::*;
 use book::*;
+use analysis::*;
+use explorer::OpeningExplorer;
+use record::*;
+use annotate::annotate_moves;
 use once_cell::sync::Lazy;
 use wasm_bindgen::prelude::*;
 
 static mut TRANSPOSITION_TABLE: Lazy<TranspositionTable> = Lazy::new(|| {TranspositionTable::new(23)});
-static mut OPENING_BOOK: Lazy<OpeningBook> = Lazy::new(|| OpeningBook::new());
+static mut OPENING_BOOK: Lazy<OpeningBook> = Lazy::new(OpeningBook::new);
+static mut OPENING_EXPLORER: Lazy<OpeningExplorer> = Lazy::new(OpeningExplorer::new);
 
 #[wasm_bindgen]
 extern "C" {
@@ -21,202 +49,210 @@ pub fn greet(name: &str) {
     alert(&format!("Hello, {}!", name));
 }
 
-#[wasm_bindgen]
-pub fn c4engine(pos: &str) -> i8{
+// wasm calls run one at a time so the shared table and book are never borrowed twice
+#[allow(static_mut_refs)]
+fn with_engine<T>(f: impl FnOnce(&mut TranspositionTable, &OpeningBook) -> T) -> T {
+    unsafe { f(&mut TRANSPOSITION_TABLE, &OPENING_BOOK) }
+}
+
+#[allow(static_mut_refs)]
+fn with_explorer<T>(f: impl FnOnce(&mut OpeningExplorer, &mut TranspositionTable, &OpeningBook) -> T) -> T {
+    unsafe { f(&mut OPENING_EXPLORER, &mut TRANSPOSITION_TABLE, &OPENING_BOOK) }
+}
+
+fn game_from_moves(pos: &str) -> Option<Game> {
     let moves = pos.chars().filter_map(|c| c.to_digit(10)).map(|d| d as u8);
-    //let mut table = TranspositionTable::new(20);
-    //let book = OpeningBook::new();
     let mut game = Game::new();
     for col_num in moves{
         if col_num>=COLS{
-            return i8::MIN;
+            return None;
         }
         if let (false, _) = game.make_move(col_num){
-            return i8::MIN;
+            return None;
         }
     }
+    Some(game)
+}
+
+#[wasm_bindgen]
+pub fn c4engine(pos: &str) -> i8{
+    //let mut table = TranspositionTable::new(20);
+    //let book = OpeningBook::new();
+    let Some(mut game) = game_from_moves(pos) else {
+        return i8::MIN;
+    };
     let mut nodes = 0;
 
-    unsafe {
-        search(&mut game, &mut TRANSPOSITION_TABLE, &OPENING_BOOK, &mut nodes)
+    with_engine(|table, book| search(&mut game, table, book, &mut nodes))
+}
+
+// Returns one byte of CELL_* flags per square (index column * 6 + row), or an empty array for an invalid position
+#[wasm_bindgen]
+pub fn c4threats(pos: &str) -> Vec<u8>{
+    match game_from_moves(pos) {
+        Some(game) => threat_analysis(&game).cell_flags(),
+        None => Vec::new(),
     }
 }
 
-#[cfg(not(target_arch = "wasm32"))]
-fn main() {
-    println!("{:?}", ZOBRIST_TABLE);
-    return;
+// Returns a JSON array with the score, refutation and best line for every legal column, or "null" for an invalid position
+#[wasm_bindgen]
+pub fn c4explain(pos: &str) -> String{
+    let Some(mut game) = game_from_moves(pos) else {
+        return "null".to_string();
+    };
+    let mut nodes = 0;
 
-    use std::{thread::sleep, time::{Duration, Instant}};
+    let explanations = with_engine(|table, book| explain_moves(&mut game, table, book, &mut nodes, 8));
+    let explanations: Vec<String> = explanations.iter().map(|explanation| explanation.to_json()).collect();
+    format!("[{}]", explanations.join(","))
+}
 
-    let mut game = Game::new();
-    let mut table = TranspositionTable::new(23);
-    let book = OpeningBook::new();
-    let mut nodes = 0;
-    
-    let start = Instant::now();
-    let eval = search(&mut game, &mut table, &book, &mut nodes);
-    let time_taken = start.elapsed();
-    println!("Eval: {}", eval);
-    println!("Time Taken: {:#?}", time_taken);
-    return;
-
-    /*let mut game = Game::new();
-    game.make_move(3);
-    let start = Instant::now();
-    
-    let mut transposition_table = TranspositionTable::new(30);
-    let mut nodes = 0;
-    println!("{}", search(&mut game, &mut transposition_table, &mut nodes));
-    let time_taken = start.elapsed();
-    println!("Time Taken: {:#?}", time_taken);
-    return;*/
+// Returns the ply 12 book entry of a position as a JSON object, or "null" if it isn't in the book or is invalid
+#[wasm_bindgen]
+pub fn c4bookentry(pos: &str) -> String{
+    let Some(game) = game_from_moves(pos) else {
+        return "null".to_string();
+    };
+    match with_engine(|_, book| book.lookup_entry(game.board_set, game.board_p1)) {
+        Some(entry) => entry.to_json(),
+        None => "null".to_string(),
+    }
+}
 
+// Returns a JSON object with the value of every continuation of a position before ply 12 and the ply 12
+// positions that weren't in the book, or "null" for an invalid position or one at ply 12 or later
+#[wasm_bindgen]
+pub fn c4explore(pos: &str) -> String{
+    let Some(mut game) = game_from_moves(pos) else {
+        return "null".to_string();
+    };
+    let mut nodes = 0;
 
-    let path = "test_cases/Test_L3_R1";
-    let (test_moves, test_evals) = read_test_file(path);
-    let mut transposition_table = TranspositionTable::new(30);
-    println!("mem {}", std::mem::size_of::<u64>());
+    match with_explorer(|explorer, table, book| explorer.explore(&mut game, table, book, &mut nodes)) {
+        Some(exploration) => exploration.to_json(),
+        None => "null".to_string(),
+    }
+}
 
-    let book = OpeningBook::new();
+// Returns a JSON array grading every move of a game from index `first` on against the solver, or "null" for an
+// illegal move list
+#[wasm_bindgen]
+pub fn c4annotate(pos: &str, first: usize) -> String{
+    let Some(moves) = pos.chars().map(|c| c.to_digit(10).map(|d| d as u8)).collect::<Option<Vec<u8>>>() else {
+        return "null".to_string();
+    };
     let mut nodes = 0;
 
-    let start = Instant::now();
-    for i in 0..1000{
-        let mut game = Game::new();
-        setup_game(&mut game, &test_moves[i]);
-        //let eval = negamax_wrapper(&mut game, 14, &mut transposition_table);
-        //let eval = negamax(&mut game, -14, 14, &mut transposition_table, &mut nodes);
-        let eval = search(&mut game, &mut transposition_table, &book, &mut nodes);
-        //println!("game {}, eval {}, answer {}", i, eval, test_evals[i]);
-        if eval != test_evals[i]{
-            println!("game {}, eval {}, answer {}", i, eval, test_evals[i]);
-            panic!("test {i} failed!");
+    match with_engine(|table, book| annotate_moves(&moves, first, table, book, &mut nodes)) {
+        Ok(annotations) => {
+            let annotations: Vec<String> = annotations.iter().map(|annotation| annotation.to_json()).collect();
+            format!("[{}]", annotations.join(","))
         }
+        Err(_) => "null".to_string(),
     }
-    let time_taken = start.elapsed();
-    println!("Mean Time Taken: {:#?}", time_taken/1000);
-    println!("Mean Nodes: {:#?}", nodes/1000);
 }
 
-#[cfg(not(target_arch = "wasm32"))]
-fn trace_pv(game: &mut Game, transposition_table: &mut TranspositionTable, book: &OpeningBook, nodes: &mut u64){
-    let mut best_col = 0;
-    let mut best_eval = 0;
-    game.print();
-
-    while best_eval != -100 {
-        best_eval = -100;
-        for i in 0..COLS{
-            if let (true, row_number) = game.make_move(i){
-                let eval = search(game, transposition_table, book, nodes);
-                println!("{i}:{} hash={}", -eval, game.get_hash());
-                if -eval > best_eval{
-                    best_eval = -eval;
-                    best_col = i;
-                }
-                game.unmake_move(i, row_number);
+// Reads one game record and returns it as JSON with the moves as 0-6 digits, or {"error": ...}
+#[wasm_bindgen]
+pub fn c4record_import(text: &str) -> String{
+    match parse_record(text) {
+        Ok(record) => record.to_json(),
+        Err(message) => format!("{{\"error\":{}}}", json_string(&message)),
+    }
+}
+
+// Writes a game record from 0-6 digit moves and tag lines such as [Player1 "Alice"]. Scores are per move with
+// i8::MIN for none, comments are one line per move with an empty line for none. Returns "" if the moves are
+// illegal or a tag is malformed.
+#[wasm_bindgen]
+pub fn c4record_export(moves: &str, tags: &str, scores: &[i8], comments: &str) -> String{
+    let Some(moves) = moves.chars().map(|c| c.to_digit(10).map(|d| d as u8)).collect::<Option<Vec<u8>>>() else {
+        return String::new();
+    };
+    let (Some(mut record), Ok(tags)) = (GameRecord::from_moves(&moves), parse_tags(tags)) else {
+        return String::new();
+    };
+    for (name, value) in tags {
+        // The result comes from the moves unless the tags claim one for an unfinished game
+        if name == "Result" && record.result == RecordResult::Unfinished {
+            match RecordResult::from_token(&value) {
+                Some(result) => record.set_result(result),
+                None => return String::new(),
             }
+        } else if name != "Result" {
+            record.set_header(&name, &value);
         }
-        if best_eval != -100{
-            game.make_move(best_col);
-            println!("move made: {}", best_col);
-            game.print();
-            println!()
-        }
+    }
+    for (recorded, &score) in record.moves.iter_mut().zip(scores) {
+        recorded.score = (score != i8::MIN).then_some(score);
+    }
+    for (recorded, comment) in record.moves.iter_mut().zip(comments.split('\n')) {
+        recorded.comment = (!comment.is_empty()).then(|| comment.to_string());
+    }
+    match record.replay() {
+        Ok(_) => record.to_text(),
+        Err(_) => String::new(),
     }
 }
 
+// The command line interface, run by the connect4enginebin binary in src/main.rs
 #[cfg(not(target_arch = "wasm32"))]
-fn read_test_file(filename: &str)->(Vec<Vec<u8>>,Vec<i8>) {
+pub use cli::run as run_cli;
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_test_file(filename: &str)->Result<(Vec<Vec<u8>>,Vec<i8>), String> {
     use std::{fs::File, io::{BufRead, BufReader}};
 
     let mut test_moves : Vec<Vec<u8>> = Vec::new();
     let mut test_evals : Vec<i8> = Vec::new();
 
-    let file = File::open(filename).expect("Couldn't find file");
+    let file = File::open(filename).map_err(|error| format!("couldn't open {filename}: {error}"))?;
     let file = BufReader::new(file);
-    for line in file.lines(){
-        let line = line.expect("couldn't read line");
-        if let Some((moves, eval)) = line.split_once(" "){
-            let moves : Vec<u8> = moves.chars()
-                .filter_map(|c| c.to_digit(10).map(|x| (x-1) as u8))
-                .collect();
-            if let Ok(eval) = eval.parse::<i8>() {
+    for (line_number, line) in file.lines().enumerate(){
+        let line = line.map_err(|error| format!("couldn't read {filename}: {error}"))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_test_line(&line) {
+            Ok((moves, Some(eval))) => {
                 test_moves.push(moves);
                 test_evals.push(eval);
-            } else {
-                panic!("Couldn't parse eval {}", eval);
             }
+            Ok((_, None)) => eprintln!("{filename}:{}: missing eval, skipping", line_number+1),
+            Err(message) => eprintln!("{filename}:{}: {message}, skipping", line_number+1),
         }
     }
-    (test_moves, test_evals)
+    Ok((test_moves, test_evals))
 }
 
+// Parses a "moves [eval]" line where moves are columns in Pons' 1-7 notation
 #[cfg(not(target_arch = "wasm32"))]
-fn setup_game(game:&mut Game, moves: &Vec<u8>){
-    for col_num in moves{
-        if let (false, _) = game.make_move(*col_num){
-            println!("{:?}", moves);
-            println!("game_status: {:#?}, moves_played{}", col_num, game.moves_made);
-            panic!("unable to make moves in test case!");
-        }
+fn parse_test_line(line: &str)->Result<(Vec<u8>, Option<i8>), String> {
+    let mut fields = line.split_whitespace();
+    let moves = fields.next().ok_or("empty line")?;
+    let moves = moves.chars()
+        .map(|c| match c.to_digit(10) {
+            Some(col_num @ 1..=7) => Ok((col_num-1) as u8),
+            _ => Err(format!("invalid column {c:?}")),
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+    let eval = match fields.next() {
+        Some(eval) => Some(eval.parse::<i8>().map_err(|_| format!("couldn't parse eval {eval}"))?),
+        None => None,
+    };
+    if fields.next().is_some() {
+        return Err("unexpected extra fields".to_string());
     }
+    Ok((moves, eval))
 }
 
-fn test_book_corrections() {
-    let book = OpeningBook::new();
-    let mut empty_book = OpeningBook::new();
-    for i in 0..BOOK_ENTRIES{
-        empty_book.positions[i] = 0;
-        empty_book.evals[i] = 0;
-    }
-    for i in 0..BOOK_ENTRIES{
-        if [-689592004,2101158888,1599634104].contains(&book.positions[i]) {
-            let mut game = Game::new();
-            let (set, p1) = decode(book.positions[i]);
-            let mut table = TranspositionTable::new(23);
-            game.board_set = set;
-            game.board_p1 = p1;
-            game.moves_made = 12;
-            let mut nodes = 0;
-            let eval = search(&mut game, &mut table, &empty_book, &mut nodes);
-            println!("pos={}, eval={} engine={}", book.positions[i], book.evals[i], eval);
+#[cfg(not(target_arch = "wasm32"))]
+fn setup_game(game:&mut Game, moves: &[u8])->Result<(), String>{
+    for &col_num in moves{
+        if let (false, _) = game.make_move(col_num){
+            return Err(format!("illegal move {} after {} moves", col_num + 1, game.moves_made));
         }
     }
+    Ok(())
 }
-
-fn test_book_code_decode(){
-    let book = OpeningBook::new();
-    for i in 0..BOOK_ENTRIES {
-        let code = book.positions[i];
-        let (set, p1) = decode(code);
-        let recode = huffman_code(set, p1, false);
-        if code != recode {
-            let mut game = Game::new();
-            println!("fail");
-            game.board_set = set;
-            game.board_p1 = p1;
-            game.print();
-            println!("{code:b}");
-            println!("{recode:b}");
-        }
-    }
-    return;
-}
-
-fn test_book_lookup(){
-    let book = OpeningBook::new();
-    for i in 0..BOOK_ENTRIES {
-        let code = book.positions[i];
-        let (set, p1) = decode(code);
-        if let Some(book_lookup) = book.lookup(set, p1){
-            if book_lookup != book.evals[i] {
-                println!("book_lookup failed code={code} {book_lookup} {}", book.evals[i]);
-                panic!();
-            }
-        } else {
-            panic!("book returned None")
-        }
-    }
-}
\ No newline at end of file
@@ -0,0 +1,4 @@
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    std::process::exit(connect4engine::run_cli(std::env::args().skip(1).collect()));
+}
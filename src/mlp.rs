@@ -0,0 +1,208 @@
+use crate::game::*;
+use crate::evaluator::Evaluator;
+use crate::training::*;
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"C4NN";
+
+// A small fully connected network scoring positions for the side to move. Its inputs are the planes the training
+// exporter writes, so a model fitted to exported shards loads as is, and every layer but the last is followed by
+// a ReLU. The first layer is kept as an accumulator updated with the few inputs each move changes, NNUE style,
+// which leaves only the small layers after it to run per evaluation.
+//
+// Weight files are little endian: "C4NN", the number of layers as a u32, then for each layer its inputs and
+// outputs as u32s, outputs * inputs f32 weights one output's row at a time, and outputs f32 biases. The first
+// layer takes SAMPLE_PLANES_LEN inputs and the last gives one output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mlp {
+    layers: Vec<Layer>,
+    // The first layer's weights by input, so an input's contribution to the accumulator is contiguous
+    input_weights: Vec<f32>,
+    // The first layer's weights summed over the side to move plane, which flips as a whole every move
+    side_to_move_weights: Vec<f32>,
+    // First layer outputs for the current position, before the activation
+    accumulator: Vec<f32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Layer {
+    inputs: usize,
+    weights: Vec<f32>,
+    biases: Vec<f32>,
+}
+
+impl Layer {
+    fn forward(&self, values: &[f32]) -> Vec<f32> {
+        self.weights.chunks_exact(self.inputs).zip(&self.biases)
+            .map(|(row, bias)| bias + row.iter().zip(values).map(|(weight, value)| weight * value).sum::<f32>())
+            .collect()
+    }
+}
+
+impl Mlp {
+    // Layers as (weights, biases), weights holding one row of inputs per output
+    pub fn new(layers: Vec<(Vec<f32>, Vec<f32>)>) -> Result<Self, String> {
+        let mut inputs = SAMPLE_PLANES_LEN;
+        let mut checked = Vec::new();
+        for (i, (weights, biases)) in layers.into_iter().enumerate() {
+            if biases.is_empty() || weights.len() != inputs * biases.len() {
+                return Err(format!("layer {i} has {} weights for {inputs} inputs and {} outputs", weights.len(), biases.len()));
+            }
+            let outputs = biases.len();
+            checked.push(Layer {inputs, weights, biases});
+            inputs = outputs;
+        }
+        if checked.is_empty() || inputs != 1 {
+            return Err("the last layer must have a single output".to_string());
+        }
+
+        let first = &checked[0];
+        let hidden = first.biases.len();
+        let mut input_weights = vec![0.0; SAMPLE_PLANES_LEN * hidden];
+        for (output, row) in first.weights.chunks_exact(SAMPLE_PLANES_LEN).enumerate() {
+            for (input, &weight) in row.iter().enumerate() {
+                input_weights[input * hidden + output] = weight;
+            }
+        }
+        let mut side_to_move_weights = vec![0.0; hidden];
+        for row in 0..ROWS as usize {
+            for col_num in 0..COLS as usize {
+                let input = plane_index(2, row, col_num);
+                for (sum, weight) in side_to_move_weights.iter_mut().zip(&input_weights[input * hidden..(input + 1) * hidden]) {
+                    *sum += weight;
+                }
+            }
+        }
+        Ok(Self {
+            accumulator: first.biases.clone(),
+            layers: checked,
+            input_weights,
+            side_to_move_weights,
+        })
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 8 || &bytes[..4] != MAGIC {
+            return Err("not a network weights file".to_string());
+        }
+        let mut at = 4;
+        let u32_field = |at: &mut usize| -> Result<usize, String> {
+            let field = bytes.get(*at..*at + 4).ok_or("truncated weights file")?;
+            *at += 4;
+            Ok(u32::from_le_bytes(field.try_into().unwrap()) as usize)
+        };
+        let layer_count = u32_field(&mut at)?;
+        let mut layers = Vec::new();
+        for _ in 0..layer_count {
+            let (inputs, outputs) = (u32_field(&mut at)?, u32_field(&mut at)?);
+            // Each layer needs 4 bytes per weight and bias, which also keeps absurd sizes from allocating
+            let floats = inputs.checked_mul(outputs).and_then(|weights| weights.checked_add(outputs))
+                .filter(|&floats| floats <= (bytes.len() - at) / 4)
+                .ok_or("truncated weights file")?;
+            let values: Vec<f32> = bytes[at..at + 4 * floats].chunks_exact(4)
+                .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+                .collect();
+            at += 4 * floats;
+            let (weights, biases) = values.split_at(inputs * outputs);
+            layers.push((weights.to_vec(), biases.to_vec()));
+        }
+        if at != bytes.len() {
+            return Err(format!("{} bytes after the last layer", bytes.len() - at));
+        }
+        Self::new(layers)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|error| format!("couldn't read {}: {error}", path.display()))?;
+        Self::from_bytes(&bytes).map_err(|error| format!("{}: {error}", path.display()))
+    }
+
+    #[cfg(test)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend((self.layers.len() as u32).to_le_bytes());
+        for layer in &self.layers {
+            bytes.extend((layer.inputs as u32).to_le_bytes());
+            bytes.extend((layer.biases.len() as u32).to_le_bytes());
+            for value in layer.weights.iter().chain(&layer.biases) {
+                bytes.extend(value.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    // Score for the side to move from a position's planes, computed from scratch
+    #[cfg(test)]
+    pub fn predict(&self, planes: &[u8; SAMPLE_PLANES_LEN]) -> f32 {
+        let inputs: Vec<f32> = planes.iter().map(|&square| square as f32).collect();
+        self.finish(self.layers[0].forward(&inputs))
+    }
+
+    // Runs the layers after the first
+    fn finish(&self, mut values: Vec<f32>) -> f32 {
+        for layer in &self.layers[1..] {
+            for value in values.iter_mut() {
+                *value = value.max(0.0);
+            }
+            values = layer.forward(&values);
+        }
+        values[0]
+    }
+
+    fn add_input(&mut self, input: usize, sign: f32) {
+        let hidden = self.accumulator.len();
+        for (sum, weight) in self.accumulator.iter_mut().zip(&self.input_weights[input * hidden..(input + 1) * hidden]) {
+            *sum += sign * weight;
+        }
+    }
+
+    // Every move flips the side to move plane
+    fn flip_side_to_move(&mut self, player_one_turn: bool) {
+        let sign = if player_one_turn {1.0} else {-1.0};
+        for (sum, weight) in self.accumulator.iter_mut().zip(&self.side_to_move_weights) {
+            *sum += sign * weight;
+        }
+    }
+
+    // Moves the playable square of a column up (sign 1) or down (sign -1) from row_number
+    fn move_playable_square(&mut self, col_num: u8, row_number: u8, sign: f32) {
+        let (col_num, row_number) = (col_num as usize, row_number as usize);
+        self.add_input(plane_index(3, row_number, col_num), -sign);
+        if row_number + 1 < ROWS as usize {
+            self.add_input(plane_index(3, row_number + 1, col_num), sign);
+        }
+    }
+}
+
+impl Evaluator for Mlp {
+    fn reset(&mut self, game: &Game) {
+        let planes = position_planes(game.board_set, game.board_set & game.board_p1, game.moves_made);
+        self.accumulator = self.layers[0].biases.clone();
+        for (input, &square) in planes.iter().enumerate() {
+            if square != 0 {
+                self.add_input(input, 1.0);
+            }
+        }
+    }
+
+    fn make_move(&mut self, game: &Game, col_num: u8, row_number: u8) {
+        // Player two's plane if player one is now to move
+        let mover_plane = game.player_one_turn as usize;
+        self.add_input(plane_index(mover_plane, row_number as usize, col_num as usize), 1.0);
+        self.flip_side_to_move(game.player_one_turn);
+        self.move_playable_square(col_num, row_number, 1.0);
+    }
+
+    fn unmake_move(&mut self, game: &Game, col_num: u8, row_number: u8) {
+        // The player to move again is the one whose stone came off
+        let mover_plane = !game.player_one_turn as usize;
+        self.add_input(plane_index(mover_plane, row_number as usize, col_num as usize), -1.0);
+        self.flip_side_to_move(game.player_one_turn);
+        self.move_playable_square(col_num, row_number, -1.0);
+    }
+
+    fn evaluate(&mut self, _game: &Game) -> f32 {
+        self.finish(self.accumulator.clone())
+    }
+}
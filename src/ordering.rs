@@ -0,0 +1,147 @@
+use crate::game::*;
+
+// Decides the order negamax tries columns in. Orders are 255 terminated like get_candidate_moves.
+pub trait MoveOrderer {
+    // tt_move is the best move stored in the transposition table for this position, if any
+    fn order_moves(&mut self, game: &mut Game, tt_move: Option<u8>) -> [u8; 7];
+
+    // Called with the position before the move when col_num caused a beta cutoff
+    fn record_cutoff(&mut self, _game: &Game, _col_num: u8) {}
+}
+
+fn playable_in_order(game: &Game, order: impl IntoIterator<Item = u8>) -> [u8; 7] {
+    let board_playable = game.get_board_playable();
+    let mut move_order = [255; 7];
+    let mut playable_cols = 0;
+    // Skips the 255 terminators of a candidate list being chained on
+    for col_num in order.into_iter().filter(|&col_num| col_num < COLS) {
+        if board_playable & (COLUMN_MASK << (8 * col_num)) != 0 && !move_order[..playable_cols].contains(&col_num) {
+            move_order[playable_cols] = col_num;
+            playable_cols += 1;
+        }
+    }
+    move_order
+}
+
+// Moves that create the most winning squares first, ties broken towards the centre
+#[derive(Default)]
+pub struct ThreatOrdering;
+
+impl MoveOrderer for ThreatOrdering {
+    fn order_moves(&mut self, game: &mut Game, _tt_move: Option<u8>) -> [u8; 7] {
+        game.get_candidate_moves()
+    }
+}
+
+// MOVE_ORDER regardless of the position
+#[derive(Default)]
+pub struct CentreOrdering;
+
+impl MoveOrderer for CentreOrdering {
+    fn order_moves(&mut self, game: &mut Game, _tt_move: Option<u8>) -> [u8; 7] {
+        playable_in_order(game, MOVE_ORDER)
+    }
+}
+
+// Squares that caused cutoffs most often, weighted towards cutoffs high in the tree
+pub struct HistoryOrdering {
+    history: [[u32; 64]; 2],
+}
+
+impl Default for HistoryOrdering {
+    fn default() -> Self {
+        Self {history: [[0; 64]; 2]}
+    }
+}
+
+impl MoveOrderer for HistoryOrdering {
+    fn order_moves(&mut self, game: &mut Game, _tt_move: Option<u8>) -> [u8; 7] {
+        let board_playable = game.get_board_playable();
+        let history = &self.history[game.player_one_turn as usize];
+        let mut col_scores = [(0, 0); 7];
+        let mut playable_cols = 0;
+        for col_num in MOVE_ORDER {
+            let column_playable = board_playable & (COLUMN_MASK << (8 * col_num));
+            if column_playable != 0 {
+                // stable_sort_moves skips a first score of i32::MAX
+                let score = history[column_playable.trailing_zeros() as usize].min(i32::MAX as u32 - 1) as i32;
+                col_scores[playable_cols] = (col_num as usize, score);
+                playable_cols += 1;
+            }
+        }
+        stable_sort_moves(col_scores, playable_cols).map(|col_num| col_num as u8)
+    }
+
+    fn record_cutoff(&mut self, game: &Game, col_num: u8) {
+        let square = (game.get_board_playable() & (COLUMN_MASK << (8 * col_num))).trailing_zeros() as usize;
+        let depth = (42 - game.moves_made) as u32;
+        let entry = &mut self.history[game.player_one_turn as usize][square];
+        *entry = entry.saturating_add(depth * depth);
+    }
+}
+
+// Up to two moves per ply that recently caused cutoffs at that ply, then threat ordering
+pub struct KillerOrdering {
+    killers: [[Option<u8>; 2]; 42],
+}
+
+impl Default for KillerOrdering {
+    fn default() -> Self {
+        Self {killers: [[None; 2]; 42]}
+    }
+}
+
+impl MoveOrderer for KillerOrdering {
+    fn order_moves(&mut self, game: &mut Game, _tt_move: Option<u8>) -> [u8; 7] {
+        let candidates = game.get_candidate_moves();
+        let killers = self.killers[game.moves_made as usize].into_iter().flatten();
+        playable_in_order(game, killers.chain(candidates))
+    }
+
+    fn record_cutoff(&mut self, game: &Game, col_num: u8) {
+        let killers = &mut self.killers[game.moves_made as usize];
+        if killers[0] != Some(col_num) {
+            killers[1] = killers[0];
+            killers[0] = Some(col_num);
+        }
+    }
+}
+
+// The transposition table's best move, then threat ordering
+#[derive(Default)]
+pub struct TableMoveOrdering;
+
+impl MoveOrderer for TableMoveOrdering {
+    fn order_moves(&mut self, game: &mut Game, tt_move: Option<u8>) -> [u8; 7] {
+        let candidates = game.get_candidate_moves();
+        playable_in_order(game, tt_move.into_iter().chain(candidates))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveOrdering {
+    Threats,
+    Centre,
+    History,
+    Killers,
+    TableMove,
+}
+
+impl MoveOrdering {
+    pub const ALL: [MoveOrdering; 5] = [MoveOrdering::Threats, MoveOrdering::Centre, MoveOrdering::History,
+        MoveOrdering::Killers, MoveOrdering::TableMove];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MoveOrdering::Threats => "threats",
+            MoveOrdering::Centre => "centre",
+            MoveOrdering::History => "history",
+            MoveOrdering::Killers => "killers",
+            MoveOrdering::TableMove => "tt",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|ordering| ordering.name() == name)
+    }
+}
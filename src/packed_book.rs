@@ -0,0 +1,269 @@
+// Compact container for the ply 12 opening book. Also compiled into build.rs, so it only uses std.
+//
+// Layout, integers little endian:
+//   magic "C4BK", version byte, eval bits byte, palette length u16, entry count u32, entries per block u16
+//   palette: every distinct bookDeepDist distance byte, ascending
+//   block index: first code (i32) and data offset (u32) of every block
+//   block data: palette indices of the block's evals packed `eval bits` wide, then the differences between
+//   consecutive codes as LEB128 varints
+// Codes are sorted, so the differences are small and a lookup only decodes the one block its code falls in.
+use std::sync::OnceLock;
+
+const MAGIC: &[u8; 4] = b"C4BK";
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 14;
+const BLOCK_ENTRIES: usize = 16;
+// Buckets of the in-memory index over the top bits of each block's first code
+const PREFIX_BITS: u32 = 16;
+
+// Packs 5 byte records of a big endian code and a distance byte, sorted by code as in bookDeepDist.dat
+pub fn pack(records: &[u8]) -> Vec<u8> {
+    let entries: Vec<(i32, u8)> = records.chunks_exact(5)
+        .map(|record| (i32::from_be_bytes([record[0], record[1], record[2], record[3]]), record[4]))
+        .collect();
+    let mut palette: Vec<u8> = entries.iter().map(|&(_, raw)| raw).collect();
+    palette.sort_unstable();
+    palette.dedup();
+    let eval_bits = usize::BITS - palette.len().saturating_sub(1).leading_zeros();
+
+    let mut index = Vec::new();
+    let mut data = Vec::new();
+    for block in entries.chunks(BLOCK_ENTRIES) {
+        index.extend(block[0].0.to_le_bytes());
+        index.extend((data.len() as u32).to_le_bytes());
+
+        let mut packed_evals = vec![0u8; (block.len() * eval_bits as usize).div_ceil(8)];
+        for (i, &(_, raw)) in block.iter().enumerate() {
+            let palette_index = palette.binary_search(&raw).unwrap();
+            for bit in 0..eval_bits as usize {
+                if palette_index >> bit & 1 == 1 {
+                    let position = i * eval_bits as usize + bit;
+                    packed_evals[position / 8] |= 1 << (position % 8);
+                }
+            }
+        }
+        data.extend(packed_evals);
+
+        for pair in block.windows(2) {
+            let mut delta = (pair[1].0 as i64 - pair[0].0 as i64) as u64;
+            while delta >= 0x80 {
+                data.push(delta as u8 | 0x80);
+                delta >>= 7;
+            }
+            data.push(delta as u8);
+        }
+    }
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.push(eval_bits as u8);
+    bytes.extend((palette.len() as u16).to_le_bytes());
+    bytes.extend((entries.len() as u32).to_le_bytes());
+    bytes.extend((BLOCK_ENTRIES as u16).to_le_bytes());
+    bytes.extend(palette);
+    bytes.extend(index);
+    bytes.extend(data);
+    bytes
+}
+
+// Reads straight from `bytes`, which can be static data, a memory map or a buffer
+pub struct PackedBook<B> {
+    bytes: B,
+    entries: usize,
+    block_entries: usize,
+    eval_bits: usize,
+    palette_start: usize,
+    index_start: usize,
+    data_start: usize,
+    // prefix_index[b] is the first block whose first code falls in bucket b or later.
+    // Built by the first lookup so loading stays free, 256KB.
+    prefix_index: OnceLock<Box<[u32]>>,
+}
+
+// Top bits of a code, flipping the sign bit so buckets follow the signed order codes are sorted in
+fn prefix_bucket(code: i32) -> usize {
+    ((code as u32 ^ 0x8000_0000) >> (32 - PREFIX_BITS)) as usize
+}
+
+impl<B: AsRef<[u8]>> PackedBook<B> {
+    // Only the header is read. An empty slice is an empty book.
+    pub fn parse(bytes: B) -> Result<Self, String> {
+        let data = bytes.as_ref();
+        if data.is_empty() {
+            return Ok(Self {bytes, entries: 0, block_entries: 1, eval_bits: 0, palette_start: 0, index_start: 0, data_start: 0,
+                prefix_index: OnceLock::new()});
+        }
+        let bytes_len = data.len();
+        let header: [u8; HEADER_LEN] = match data.get(..HEADER_LEN) {
+            Some(header) => header.try_into().unwrap(),
+            None => return Err("not a packed book".to_string()),
+        };
+        if &header[..4] != MAGIC {
+            return Err("not a packed book".to_string());
+        }
+        if header[4] != VERSION {
+            return Err(format!("unsupported packed book version {}", header[4]));
+        }
+        let eval_bits = header[5] as usize;
+        let palette_len = u16::from_le_bytes([header[6], header[7]]) as usize;
+        let entries = u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
+        let block_entries = u16::from_le_bytes([header[12], header[13]]) as usize;
+        if eval_bits > 8 || block_entries == 0 {
+            return Err("corrupt packed book header".to_string());
+        }
+        let index_start = HEADER_LEN + palette_len;
+        let data_start = index_start + entries.div_ceil(block_entries) * 8;
+        if bytes_len < data_start {
+            return Err("truncated packed book".to_string());
+        }
+        Ok(Self {bytes, entries, block_entries, eval_bits, palette_start: HEADER_LEN, index_start, data_start,
+            prefix_index: OnceLock::new()})
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    // Bytes held, header and index included
+    pub fn size(&self) -> usize {
+        self.bytes.as_ref().len()
+    }
+
+    fn prefix_index(&self) -> &[u32] {
+        self.prefix_index.get_or_init(|| {
+            let mut prefix_index = vec![0u32; (1 << PREFIX_BITS) + 1];
+            for block in 0..self.block_count() {
+                if let Some(code) = self.block_first_code(block) {
+                    prefix_index[prefix_bucket(code) + 1] += 1;
+                }
+            }
+            for bucket in 1..prefix_index.len() {
+                prefix_index[bucket] += prefix_index[bucket - 1];
+            }
+            prefix_index.into_boxed_slice()
+        })
+    }
+
+    fn block_count(&self) -> usize {
+        self.entries.div_ceil(self.block_entries)
+    }
+
+    fn read_u32(&self, at: usize) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes.as_ref().get(at..at + 4)?.try_into().ok()?))
+    }
+
+    fn block_first_code(&self, block: usize) -> Option<i32> {
+        self.read_u32(self.index_start + 8 * block).map(|code| code as i32)
+    }
+
+    // Number of entries, start of the packed evals and start of the code differences of a block
+    fn block_layout(&self, block: usize) -> Option<(usize, usize, usize)> {
+        let count = self.block_entries.min(self.entries.checked_sub(block * self.block_entries)?);
+        let evals_start = self.data_start + self.read_u32(self.index_start + 8 * block + 4)? as usize;
+        Some((count, evals_start, evals_start + (count * self.eval_bits).div_ceil(8)))
+    }
+
+    fn eval_at(&self, evals_start: usize, i: usize) -> Option<i8> {
+        let mut palette_index = 0;
+        for bit in 0..self.eval_bits {
+            let position = i * self.eval_bits + bit;
+            let byte = *self.bytes.as_ref().get(evals_start + position / 8)?;
+            palette_index |= ((byte >> (position % 8) & 1) as usize) << bit;
+        }
+        self.bytes.as_ref().get(self.palette_start + palette_index).map(|&raw| raw as i8)
+    }
+
+    // Calls `visit` with each entry's position in the block and code until it returns false
+    fn scan_block(&self, block: usize, mut visit: impl FnMut(usize, i32) -> bool) -> Option<()> {
+        let (count, _, deltas_start) = self.block_layout(block)?;
+        let mut deltas = self.bytes.as_ref().get(deltas_start..)?.iter();
+        let mut code = self.block_first_code(block)? as i64;
+        for i in 0..count {
+            if i > 0 {
+                let mut delta = 0u64;
+                for shift in (0..64).step_by(7) {
+                    let byte = *deltas.next()?;
+                    delta |= ((byte & 0x7f) as u64) << shift;
+                    if byte & 0x80 == 0 {
+                        break;
+                    }
+                }
+                code += delta as i64;
+            }
+            if !visit(i, code as i32) {
+                break;
+            }
+        }
+        Some(())
+    }
+
+    // Distance byte stored for `code`, decoding only the block it would be in
+    pub fn find(&self, code: i32) -> Option<i8> {
+        self.find_entry(code).map(|(_, raw)| raw)
+    }
+
+    // Index of `code` in code order and its distance byte
+    pub fn find_entry(&self, code: i32) -> Option<(usize, i8)> {
+        // Last block whose first code is at most `code`. It starts in the code's bucket, unless no block does
+        // and it's the last block of an earlier bucket.
+        let bucket = prefix_bucket(code);
+        if self.is_empty() {
+            return None;
+        }
+        let prefix_index = self.prefix_index();
+        let mut low = *prefix_index.get(bucket)? as usize;
+        let mut high = *prefix_index.get(bucket + 1)? as usize;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.block_first_code(mid)? <= code {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let block = low.checked_sub(1)?;
+        let mut found = None;
+        self.scan_block(block, |i, entry_code| {
+            if entry_code == code {
+                found = Some(i);
+            }
+            entry_code < code
+        })?;
+        let found = found?;
+        let (_, evals_start, _) = self.block_layout(block)?;
+        Some((block * self.block_entries + found, self.eval_at(evals_start, found)?))
+    }
+
+    // The (code, distance byte) at `index` in code order
+    pub fn entry(&self, index: usize) -> Option<(i32, i8)> {
+        if index >= self.entries {
+            return None;
+        }
+        let block = index / self.block_entries;
+        let mut code = None;
+        self.scan_block(block, |i, entry_code| {
+            code = Some(entry_code);
+            i < index % self.block_entries
+        })?;
+        let (_, evals_start, _) = self.block_layout(block)?;
+        Some((code?, self.eval_at(evals_start, index % self.block_entries)?))
+    }
+
+    // Every (code, distance byte) in code order
+    pub fn entries(&self) -> impl Iterator<Item = (i32, i8)> + '_ {
+        (0..self.block_count()).flat_map(move |block| {
+            let mut codes = Vec::with_capacity(self.block_entries);
+            self.scan_block(block, |_, code| {
+                codes.push(code);
+                true
+            });
+            let evals_start = self.block_layout(block).map_or(0, |(_, evals_start, _)| evals_start);
+            codes.into_iter().enumerate()
+                .filter_map(move |(i, code)| Some((code, self.eval_at(evals_start, i)?)))
+        })
+    }
+}
@@ -0,0 +1,101 @@
+use crate::game::*;
+use std::collections::HashMap;
+
+// Published counts from the empty board. Move paths are OEIS A090224 and unique positions OEIS A212693.
+pub const PUBLISHED_PATHS: [u64; 11] = [1, 7, 49, 343, 2401, 16807, 117649, 823536, 5673234, 39394572, 268031646];
+pub const PUBLISHED_POSITIONS: [u64; 13] = [1, 7, 49, 238, 1120, 4263, 16422, 54859, 184275, 558186, 1662623, 4568683, 12236101];
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PlyStats {
+    pub ply: i8,
+    // Move sequences of this length. Finished games aren't extended.
+    pub paths: u64,
+    pub positions: u64,
+    // Positions counting a board and its mirror image once
+    pub mirror_positions: u64,
+    // Positions at this ply where the game has just ended
+    pub player1_wins: u64,
+    pub player2_wins: u64,
+    pub draws: u64,
+}
+
+impl PlyStats {
+    // Whether the counts agree with the published sequences, None if nothing is published for this ply
+    pub fn matches_published(&self) -> Option<bool> {
+        let ply = self.ply as usize;
+        let paths = PUBLISHED_PATHS.get(ply).map(|paths| *paths == self.paths);
+        let positions = PUBLISHED_POSITIONS.get(ply).map(|positions| *positions == self.positions);
+        match (paths, positions) {
+            (None, None) => None,
+            (paths, positions) => Some(paths.unwrap_or(true) && positions.unwrap_or(true)),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!("{{\"ply\":{},\"paths\":{},\"positions\":{},\"mirror_positions\":{},\"player1_wins\":{},\"player2_wins\":{},\"draws\":{}}}",
+            self.ply, self.paths, self.positions, self.mirror_positions, self.player1_wins, self.player2_wins, self.draws)
+    }
+}
+
+// Exact key for a position, stones and owners side by side
+fn position_key(board_set: u64, board_p1: u64) -> u128 {
+    (board_set as u128) << 64 | (board_set & board_p1) as u128
+}
+
+fn game_from_key(key: u128) -> Game {
+    let mut game = Game::new();
+    game.board_set = (key >> 64) as u64;
+    game.board_p1 = key as u64;
+    game.moves_made = game.board_set.count_ones() as i8;
+    game.player_one_turn = game.moves_made % 2 == 0;
+    game
+}
+
+// Walks the game tree breadth first, one ply at a time, keeping each unique position once along with the
+// number of move paths that reach it. Memory grows with the number of positions, about 12 million at ply 12.
+pub fn perft(max_ply: i8) -> Vec<PlyStats> {
+    let mut stats = Vec::new();
+    let mut frontier: HashMap<u128, u64> = HashMap::from([(position_key(0, 0), 1)]);
+    stats.push(PlyStats {ply: 0, paths: 1, positions: 1, mirror_positions: 1, ..Default::default()});
+
+    for ply in 1..=max_ply {
+        let mut next: HashMap<u128, u64> = HashMap::new();
+        let mut ply_stats = PlyStats {ply, ..Default::default()};
+        let mut finished: HashMap<u128, GameStatus> = HashMap::new();
+
+        for (&key, &paths) in &frontier {
+            let mut game = game_from_key(key);
+            for col_num in 0..COLS {
+                if let (true, row_number) = game.make_move(col_num) {
+                    let child = position_key(game.board_set, game.board_p1);
+                    ply_stats.paths += paths;
+                    if game.game_status == GameStatus::InProgress {
+                        *next.entry(child).or_default() += paths;
+                    } else {
+                        finished.entry(child).or_insert(game.game_status);
+                    }
+                    game.unmake_move(col_num, row_number);
+                }
+            }
+        }
+
+        for status in finished.values() {
+            match status {
+                GameStatus::Player1Win => ply_stats.player1_wins += 1,
+                GameStatus::Player2Win => ply_stats.player2_wins += 1,
+                _ => ply_stats.draws += 1,
+            }
+        }
+        ply_stats.positions = (next.len() + finished.len()) as u64;
+        // A mirror pair is counted once through its smaller key, a symmetric board once through itself
+        ply_stats.mirror_positions = next.keys().chain(finished.keys())
+            .filter(|&&key| {
+                let game = game_from_key(key);
+                key <= position_key(mirror_board(game.board_set), mirror_board(game.board_p1))
+            })
+            .count() as u64;
+        stats.push(ply_stats);
+        frontier = next;
+    }
+    stats
+}
@@ -0,0 +1,99 @@
+use crate::game::*;
+use crate::engine::*;
+use crate::book::*;
+use crate::random::Rng;
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PositionOptions {
+    // Moves played to reach each position
+    pub ply: i8,
+    // Skip positions where the player to move has a winning move on the board
+    pub exclude_immediate_wins: bool,
+}
+
+impl Default for PositionOptions {
+    fn default() -> Self {
+        Self {
+            ply: 8,
+            exclude_immediate_wins: true,
+        }
+    }
+}
+
+// Rejected games in a row after which the options are taken to be unsatisfiable, e.g. more positions asked
+// for than exist at a low ply
+pub const MAX_REJECTIONS: usize = 100_000;
+
+// Plays columns picked uniformly among the playable ones until `ply` moves are made, appending them to `moves`.
+// False if the game ends first.
+pub fn play_random_moves(game: &mut Game, moves: &mut String, rng: &mut Rng, ply: i8) -> bool {
+    while game.moves_made < ply {
+        if game.game_status != GameStatus::InProgress {
+            return false;
+        }
+        let playable: Vec<u8> = (0..COLS)
+            .filter(|col_num| game.get_board_playable() & (COLUMN_MASK << (8 * col_num)) != 0)
+            .collect();
+        let col_num = playable[rng.below(playable.len() as u64) as usize];
+        game.make_move(col_num);
+        moves.push_str(&col_num.to_string());
+    }
+    game.game_status == GameStatus::InProgress
+}
+
+// A random game of `ply` moves from the empty board. None if the game ends first.
+pub fn random_playout(rng: &mut Rng, ply: i8) -> Option<(Game, String)> {
+    let mut game = Game::new();
+    let mut moves = String::new();
+    play_random_moves(&mut game, &mut moves, rng, ply).then_some((game, moves))
+}
+
+// Distinct positions still in progress, as 0-6 digit moves, filtered by `keep`. Fewer than `count` come back
+// when MAX_REJECTIONS games in a row are rejected.
+fn generate(count: usize, options: &PositionOptions, seed: u64, mut keep: impl FnMut(&mut Game) -> bool) -> Vec<String> {
+    let mut rng = Rng::new(seed);
+    let mut seen = HashSet::new();
+    let mut positions = Vec::new();
+    let mut rejections = 0;
+    while positions.len() < count && rejections < MAX_REJECTIONS && (0..42).contains(&options.ply) {
+        let kept = random_playout(&mut rng, options.ply).filter(|(game, _)| {
+            (!options.exclude_immediate_wins || game.get_winning_move().is_none())
+                && !seen.contains(&(game.board_set, game.board_set & game.board_p1))
+        });
+        let Some((mut game, moves)) = kept else {
+            rejections += 1;
+            continue;
+        };
+        if keep(&mut game) {
+            seen.insert((game.board_set, game.board_set & game.board_p1));
+            positions.push(moves);
+            rejections = 0;
+        } else {
+            rejections += 1;
+        }
+    }
+    positions
+}
+
+// Seeded random positions at options.ply that aren't decided yet
+pub fn random_positions(count: usize, options: &PositionOptions, seed: u64) -> Vec<String> {
+    generate(count, options, seed, |_| true)
+}
+
+// Like random_positions, keeping only positions whose solver score for the player to move is within
+// min_score..=max_score. Each position found is searched, so narrow ranges at low plies are slow.
+#[allow(clippy::too_many_arguments)]
+pub fn random_scored_positions(count: usize, options: &PositionOptions, min_score: i8, max_score: i8, seed: u64,
+        transposition_table: &mut TranspositionTable, book: &OpeningBook, nodes: &mut u64) -> Vec<(String, i8)> {
+    let mut scores = Vec::new();
+    let positions = generate(count, options, seed, |game| {
+        let score = search(game, transposition_table, book, nodes);
+        let kept = (min_score..=max_score).contains(&score);
+        if kept {
+            scores.push(score);
+        }
+        kept
+    });
+    positions.into_iter().zip(scores).collect()
+}
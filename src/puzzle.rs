@@ -0,0 +1,259 @@
+use crate::game::*;
+use crate::engine::*;
+use crate::book::*;
+use crate::random::Rng;
+use std::collections::HashSet;
+
+// "Win in N" puzzles: the player to move wins with their Nth move against any defence, and only one column
+// wins within the generator's limit at all. A win with the kth own move from a position after m moves scores 22 - k - m/2, the same
+// 21 - moves_made/2 convention as an immediate win, so N is read straight off the solver score.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+    Expert,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 4] = [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard, Difficulty::Expert];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+            Difficulty::Expert => "expert",
+        }
+    }
+
+    #[cfg(test)]
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|difficulty| difficulty.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Puzzle {
+    // 0-6 digits reaching the position
+    pub moves: String,
+    pub win_in: u8,
+    pub solution: u8,
+    // Best play from the position to the win, starting with the solution
+    pub line: Vec<u8>,
+    // Other columns that still win, only too slowly to count as solutions
+    pub alternatives: u8,
+    // Where the solution comes in the engine's threat ordering, 0 if it's the first move tried
+    pub solution_rank: u8,
+    pub difficulty: Difficulty,
+}
+
+fn grade(win_in: u8, solution_rank: u8) -> Difficulty {
+    let mut level = match win_in {
+        0..=2 => 0,
+        3..=4 => 1,
+        5..=6 => 2,
+        _ => 3,
+    };
+    // A solution the threat heuristic doesn't suggest is harder to spot, except in a win in one
+    if win_in > 1 && solution_rank >= 2 {
+        level += 1;
+    }
+    Difficulty::ALL[level.min(3)]
+}
+
+fn digits(moves: &[u8]) -> String {
+    moves.iter().map(|col_num| col_num.to_string()).collect()
+}
+
+impl Puzzle {
+    // "moves win_in solution line difficulty alternatives solution_rank", moves and line as 0-6 digits
+    pub fn to_line(&self) -> String {
+        format!("{} {} {} {} {} {} {}", self.moves, self.win_in, self.solution, digits(&self.line), self.difficulty.name(),
+            self.alternatives, self.solution_rank)
+    }
+
+    // Reads a line written by to_line, checking the moves are legal
+    #[cfg(test)]
+    pub fn from_line(line: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [moves, win_in, solution, solution_line, difficulty, alternatives, solution_rank] = fields[..] else {
+            return Err(format!("expected 7 fields: {line}"));
+        };
+        let number = |field: &str| field.parse::<u8>().map_err(|_| format!("bad number {field:?}"));
+        let columns = |field: &str| field.chars()
+            .map(|c| c.to_digit(10).filter(|&d| d < COLS as u32).map(|d| d as u8).ok_or(format!("bad column {c:?}")))
+            .collect::<Result<Vec<u8>, String>>();
+        columns(moves)?;
+        let puzzle = Puzzle {
+            moves: moves.to_string(),
+            win_in: number(win_in)?,
+            solution: number(solution)?,
+            line: columns(solution_line)?,
+            difficulty: Difficulty::from_name(difficulty).ok_or(format!("unknown difficulty {difficulty:?}"))?,
+            alternatives: number(alternatives)?,
+            solution_rank: number(solution_rank)?,
+        };
+        if crate::game_from_moves(moves).is_none() {
+            return Err(format!("illegal moves {moves}"));
+        }
+        Ok(puzzle)
+    }
+
+    // The same fields as to_line as a JSON object, with the line as an array of columns
+    pub fn to_json(&self) -> String {
+        let line: Vec<String> = self.line.iter().map(|col_num| col_num.to_string()).collect();
+        format!("{{\"moves\":\"{}\",\"win_in\":{},\"solution\":{},\"line\":[{}],\"difficulty\":\"{}\",\"alternatives\":{},\"solution_rank\":{}}}",
+            self.moves, self.win_in, self.solution, line.join(","), self.difficulty.name(), self.alternatives, self.solution_rank)
+    }
+}
+
+// Own moves the player to move needs to win against best defence, None unless they win
+pub fn moves_to_win(moves_made: i8, score: i8) -> Option<u8> {
+    (score > 0).then(|| (22 - moves_made/2 - score) as u8)
+}
+
+// The puzzle the position makes if the player to move wins in at most `max_win_in` moves and no other column
+// wins that fast. `moves` reaches the position and the game is restored before returning.
+pub fn puzzle_at(game: &mut Game, moves: &str, max_win_in: u8, transposition_table: &mut TranspositionTable,
+        book: &OpeningBook, nodes: &mut u64) -> Option<Puzzle> {
+    if game.game_status != GameStatus::InProgress {
+        return None;
+    }
+    let score = search(game, transposition_table, book, nodes);
+    let win_in = moves_to_win(game.moves_made, score).filter(|&win_in| win_in <= max_win_in)?;
+
+    let scores = column_scores(game, transposition_table, book, nodes);
+    let wins_within = |col_score: Option<i8>| col_score
+        .and_then(|col_score| moves_to_win(game.moves_made, col_score))
+        .is_some_and(|col_win_in| col_win_in <= max_win_in);
+    let mut winning = (0..COLS).filter(|&col_num| wins_within(scores[col_num as usize]));
+    let solution = winning.next()?;
+    if winning.next().is_some() {
+        return None;
+    }
+    let alternatives = scores.iter().filter(|&&col_score| col_score.is_some_and(|col_score| col_score > 0)).count() as u8 - 1;
+    let solution_rank = game.get_candidate_moves().iter().position(|&col_num| col_num == solution).unwrap_or(COLS as usize) as u8;
+
+    let (_, row_number) = game.make_move(solution);
+    let mut line = vec![solution];
+    line.extend(principal_variation(game, transposition_table, book, nodes));
+    game.unmake_move(solution, row_number);
+
+    Some(Puzzle {
+        moves: moves.to_string(),
+        win_in,
+        solution,
+        line,
+        alternatives,
+        solution_rank,
+        difficulty: grade(win_in, solution_rank),
+    })
+}
+
+const MIN_PUZZLE_PLY: usize = 12;
+// The most own moves a win can take from MIN_PUZZLE_PLY
+pub const MAX_WIN_IN: u8 = 21 - MIN_PUZZLE_PLY as u8 / 2;
+// Games in a row without a puzzle after which generation gives up, e.g. when every nearby position was used
+pub const MAX_FRUITLESS_GAMES: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PuzzleSource {
+    // Random games from the empty board
+    Playouts,
+    // Random games continued from positions in the ply 12 book
+    Book,
+}
+
+// Undoes stones from the top of the board back to the empty board, always the last mover's and never through a
+// finished position, giving moves that reach it. None if no order works.
+pub fn moves_for_position(board_set: u64, p1_squares: u64) -> Option<Vec<u8>> {
+    if board_set == 0 {
+        return Some(Vec::new());
+    }
+    let last_mover = if board_set.count_ones() % 2 == 1 {p1_squares} else {board_set & !p1_squares};
+    for col_num in MOVE_ORDER {
+        let column = board_set & (COLUMN_MASK << (8 * col_num));
+        if column == 0 {
+            continue;
+        }
+        let top = 1u64 << (63 - column.leading_zeros());
+        if last_mover & top == 0 {
+            continue;
+        }
+        let (played, p1) = (board_set & !top, p1_squares & !top);
+        if check_board_for_win(p1) || check_board_for_win(played & !p1) {
+            continue;
+        }
+        if let Some(mut moves) = moves_for_position(played, p1) {
+            moves.push(col_num);
+            return Some(moves);
+        }
+    }
+    None
+}
+
+// Plays random games and checks their last few positions, where forced wins are short, for puzzles with
+// win_in between min_win_in and max_win_in. Each game gives at most one puzzle and positions are only used once.
+// Reproducible for a given seed. Fails unless 1 <= min_win_in <= max_win_in and min_win_in <= MAX_WIN_IN, or
+// if MAX_FRUITLESS_GAMES games in a row give no puzzle.
+#[allow(clippy::too_many_arguments)]
+pub fn generate_puzzles(count: usize, min_win_in: u8, max_win_in: u8, source: PuzzleSource, seed: u64,
+        transposition_table: &mut TranspositionTable, book: &OpeningBook, nodes: &mut u64) -> Result<Vec<Puzzle>, String> {
+    if min_win_in == 0 || min_win_in > max_win_in || min_win_in > MAX_WIN_IN {
+        return Err(format!("no puzzles win in {min_win_in} to {max_win_in} moves, the range must lie within 1 to {MAX_WIN_IN}"));
+    }
+    let mut rng = Rng::new(seed);
+    let mut puzzles = Vec::new();
+    let mut seen = HashSet::new();
+    let mut fruitless_games = 0;
+    // A book without entries can't start any games
+    let source = if book.is_empty() {PuzzleSource::Playouts} else {source};
+    while puzzles.len() < count {
+        if fruitless_games == MAX_FRUITLESS_GAMES {
+            return Err(format!("only found {} puzzles before {MAX_FRUITLESS_GAMES} games in a row gave none", puzzles.len()));
+        }
+        fruitless_games += 1;
+        let mut moves = match source {
+            PuzzleSource::Playouts => Vec::new(),
+            PuzzleSource::Book => {
+                let (code, _) = book.entry(rng.below(book.len() as u64) as usize).expect("index is below the book's length");
+                let (board_set, board_p1) = decode(code);
+                let Some(moves) = moves_for_position(board_set, board_set & board_p1) else { continue };
+                moves
+            }
+        };
+        let mut game = Game::new();
+        for &col_num in &moves {
+            game.make_move(col_num);
+        }
+        while game.game_status == GameStatus::InProgress {
+            let col_num = rng.below(COLS as u64) as u8;
+            if let (true, _) = game.make_move(col_num) {
+                moves.push(col_num);
+            }
+        }
+
+        // A win in N takes 2N-1 plies, check a few more in case the random game missed a faster win.
+        // Positions before MIN_PUZZLE_PLY are skipped as they're slow to solve and rarely short wins.
+        let end = moves.len();
+        let start = end.saturating_sub(2 * max_win_in as usize + 2).max(MIN_PUZZLE_PLY);
+        let mut game = Game::new();
+        for &col_num in &moves[..start.min(end)] {
+            game.make_move(col_num);
+        }
+        for ply in start..end {
+            if seen.insert((game.board_set, game.board_set & game.board_p1)) {
+                let puzzle = puzzle_at(&mut game, &digits(&moves[..ply]), max_win_in, transposition_table, book, nodes);
+                if let Some(puzzle) = puzzle.filter(|puzzle| puzzle.win_in >= min_win_in) {
+                    puzzles.push(puzzle);
+                    fruitless_games = 0;
+                    break;
+                }
+            }
+            game.make_move(moves[ply]);
+        }
+    }
+    Ok(puzzles)
+}
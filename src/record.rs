@@ -0,0 +1,405 @@
+use crate::game::*;
+
+// Game records in a PGN-like text format. A record is tag lines, a blank line, then the move text:
+//
+//   [Player1 "Alice"]
+//   [Player2 "c4engine"]
+//   [Date "2026.10.18"]
+//   [Result "0-1"]
+//   [Ruleset "standard"]
+//   [Board "7x6"]
+//
+//   1. 4 (+1) {the centre} 4 2. 5 4 (-3) {missed the threat} ... 0-1
+//
+// Moves are columns 1-7 from the left as in Pons' test files, each optionally followed by a score in brackets
+// for the player who made it and a comment in braces. Move numbers count pairs of moves and are optional
+// when parsing. Several records can follow one another in a file.
+
+pub const STANDARD_RULESET: &str = "standard";
+pub const STANDARD_BOARD: &str = "7x6";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordResult {
+    Player1Win,
+    Player2Win,
+    Draw,
+    // Still being played, or abandoned
+    Unfinished,
+}
+
+impl RecordResult {
+    pub fn token(self) -> &'static str {
+        match self {
+            RecordResult::Player1Win => "1-0",
+            RecordResult::Player2Win => "0-1",
+            RecordResult::Draw => "1/2-1/2",
+            RecordResult::Unfinished => "*",
+        }
+    }
+
+    pub fn from_token(token: &str) -> Option<Self> {
+        match token {
+            "1-0" => Some(RecordResult::Player1Win),
+            "0-1" => Some(RecordResult::Player2Win),
+            "1/2-1/2" => Some(RecordResult::Draw),
+            "*" => Some(RecordResult::Unfinished),
+            _ => None,
+        }
+    }
+
+    fn from_status(status: &GameStatus) -> Self {
+        match status {
+            GameStatus::Player1Win => RecordResult::Player1Win,
+            GameStatus::Player2Win => RecordResult::Player2Win,
+            GameStatus::Draw => RecordResult::Draw,
+            GameStatus::InProgress => RecordResult::Unfinished,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedMove {
+    // 0-6 like Game::make_move, written 1-7
+    pub col_num: u8,
+    // Score from the point of view of the player making this move
+    pub score: Option<i8>,
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameRecord {
+    // Tags in the order they're written. The Result tag is kept in step with `result`.
+    pub headers: Vec<(String, String)>,
+    pub moves: Vec<RecordedMove>,
+    // A finished game's result always matches the board. An unfinished game may still claim a result,
+    // e.g. after a resignation.
+    pub result: RecordResult,
+}
+
+impl GameRecord {
+    // Replays 0-6 columns, None if one is illegal
+    pub fn from_moves(moves: &[u8]) -> Option<Self> {
+        let mut record = GameRecord {
+            headers: vec![("Ruleset".to_string(), STANDARD_RULESET.to_string()), ("Board".to_string(), STANDARD_BOARD.to_string())],
+            moves: moves.iter().map(|&col_num| RecordedMove {col_num, score: None, comment: None}).collect(),
+            result: RecordResult::Unfinished,
+        };
+        let game = record.play().ok()?;
+        record.set_result(RecordResult::from_status(&game.game_status));
+        Some(record)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    // Replaces the tag if present, otherwise adds it at the end
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        match self.headers.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, held)) => *held = value.to_string(),
+            None => self.headers.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn set_result(&mut self, result: RecordResult) {
+        self.result = result;
+        self.set_header("Result", result.token());
+    }
+
+    // Moves as the 0-6 digit string c4engine takes
+    pub fn move_string(&self) -> String {
+        self.moves.iter().map(|recorded| recorded.col_num.to_string()).collect()
+    }
+
+    // Plays the moves through Game::make_move, checking the tags and result agree with them
+    pub fn replay(&self) -> Result<Game, String> {
+        if let Some(ruleset) = self.header("Ruleset") {
+            if ruleset != STANDARD_RULESET {
+                return Err(format!("unsupported ruleset {ruleset:?}"));
+            }
+        }
+        if let Some(board) = self.header("Board") {
+            if board != STANDARD_BOARD {
+                return Err(format!("unsupported board size {board:?}"));
+            }
+        }
+        let game = self.play()?;
+        if game.game_status != GameStatus::InProgress && RecordResult::from_status(&game.game_status) != self.result {
+            return Err(format!("result {} doesn't match the final position", self.result.token()));
+        }
+        if let Some(tag) = self.header("Result") {
+            if tag != self.result.token() {
+                return Err(format!("Result tag {tag:?} doesn't match the move text's {}", self.result.token()));
+            }
+        }
+        Ok(game)
+    }
+
+    fn play(&self) -> Result<Game, String> {
+        let mut game = Game::new();
+        for (i, recorded) in self.moves.iter().enumerate() {
+            if game.game_status != GameStatus::InProgress {
+                return Err(format!("move {} is played after the game ended", i + 1));
+            }
+            if recorded.col_num >= COLS {
+                return Err(format!("move {}: no column {}", i + 1, recorded.col_num as u32 + 1));
+            }
+            if let (false, _) = game.make_move(recorded.col_num) {
+                return Err(format!("move {}: column {} is full", i + 1, recorded.col_num + 1));
+            }
+        }
+        Ok(game)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (name, value) in &self.headers {
+            text.push_str(&format!("[{name} \"{}\"]\n", escape(value, '"')));
+        }
+        text.push('\n');
+
+        let mut tokens = Vec::new();
+        for (i, recorded) in self.moves.iter().enumerate() {
+            if i % 2 == 0 {
+                tokens.push(format!("{}.", i / 2 + 1));
+            }
+            tokens.push((recorded.col_num + 1).to_string());
+            if let Some(score) = recorded.score {
+                tokens.push(format!("({score:+})").replace("(+0)", "(0)"));
+            }
+            if let Some(comment) = &recorded.comment {
+                tokens.push(format!("{{{}}}", escape(comment, '}')));
+            }
+        }
+        tokens.push(self.result.token().to_string());
+
+        // Wrapped like PGN, comments are never split
+        let mut line_length = 0;
+        for token in tokens {
+            if line_length > 0 && line_length + 1 + token.len() > 79 {
+                text.push('\n');
+                line_length = 0;
+            } else if line_length > 0 {
+                text.push(' ');
+                line_length += 1;
+            }
+            line_length += token.len();
+            text.push_str(&token);
+        }
+        text.push('\n');
+        text
+    }
+
+    // Headers, moves and result as a JSON object, moves as a 0-6 digit string
+    pub fn to_json(&self) -> String {
+        let headers: Vec<String> = self.headers.iter()
+            .map(|(name, value)| format!("{}:{}", json_string(name), json_string(value)))
+            .collect();
+        let scores: Vec<String> = self.moves.iter()
+            .map(|recorded| recorded.score.map_or("null".to_string(), |score| score.to_string()))
+            .collect();
+        let comments: Vec<String> = self.moves.iter()
+            .map(|recorded| recorded.comment.as_deref().map_or("null".to_string(), json_string))
+            .collect();
+        format!("{{\"headers\":{{{}}},\"moves\":\"{}\",\"scores\":[{}],\"comments\":[{}],\"result\":\"{}\"}}",
+            headers.join(","), self.move_string(), scores.join(","), comments.join(","), self.result.token())
+    }
+}
+
+// Backslash escapes `quote` and backslashes
+fn escape(text: &str, quote: char) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == quote || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+pub fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+    text: &'a str,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<char> {
+        let (_, c) = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|(_, c)| c.is_whitespace()) {
+            self.next();
+        }
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("line {}: {message}", self.line)
+    }
+
+    // Text up to an unescaped `close`, the opening character already consumed
+    fn delimited(&mut self, close: char, what: &str) -> Result<String, String> {
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('\\') => match self.next() {
+                    Some(c) => value.push(c),
+                    None => return Err(self.error(&format!("unterminated {what}"))),
+                },
+                Some(c) if c == close => return Ok(value),
+                Some(c) => value.push(c),
+                None => return Err(self.error(&format!("unterminated {what}"))),
+            }
+        }
+    }
+
+    fn word(&mut self) -> &str {
+        let start = self.chars.peek().map_or(self.text.len(), |&(i, _)| i);
+        while self.chars.peek().is_some_and(|&(_, c)| !c.is_whitespace() && !"{([".contains(c)) {
+            self.next();
+        }
+        let end = self.chars.peek().map_or(self.text.len(), |&(i, _)| i);
+        &self.text[start..end]
+    }
+
+    fn tag(&mut self) -> Result<(String, String), String> {
+        if self.next() != Some('[') {
+            return Err(self.error("expected a tag"));
+        }
+        self.skip_whitespace();
+        let name = self.word().to_string();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(self.error(&format!("bad tag name {name:?}")));
+        }
+        self.skip_whitespace();
+        if self.next() != Some('"') {
+            return Err(self.error(&format!("tag {name} needs a quoted value")));
+        }
+        let value = self.delimited('"', "tag value")?;
+        self.skip_whitespace();
+        if self.next() != Some(']') {
+            return Err(self.error(&format!("tag {name} isn't closed")));
+        }
+        Ok((name, value))
+    }
+
+    fn record(&mut self) -> Result<GameRecord, String> {
+        let mut record = GameRecord {headers: Vec::new(), moves: Vec::new(), result: RecordResult::Unfinished};
+        self.skip_whitespace();
+        while self.chars.peek().is_some_and(|&(_, c)| c == '[') {
+            let (name, value) = self.tag()?;
+            if record.header(&name).is_some() {
+                return Err(self.error(&format!("tag {name} appears twice")));
+            }
+            record.headers.push((name, value));
+            self.skip_whitespace();
+        }
+
+        loop {
+            self.skip_whitespace();
+            match self.chars.peek().map(|&(_, c)| c) {
+                None => return Err(self.error("move text doesn't end with a result")),
+                Some('(') => {
+                    self.next();
+                    let score = self.delimited(')', "score")?;
+                    let Some(recorded) = record.moves.last_mut() else {
+                        return Err(self.error("score before the first move"));
+                    };
+                    recorded.score = Some(score.trim().parse().map_err(|_| self.error(&format!("bad score {score:?}")))?);
+                }
+                Some('{') => {
+                    self.next();
+                    let comment = self.delimited('}', "comment")?;
+                    let Some(recorded) = record.moves.last_mut() else {
+                        return Err(self.error("comment before the first move"));
+                    };
+                    recorded.comment = Some(comment);
+                }
+                Some(_) => {
+                    let word = self.word().to_string();
+                    if let Some(result) = RecordResult::from_token(&word) {
+                        record.result = result;
+                        break;
+                    }
+                    // Move numbers, "1." or "1...", aren't checked against the move count and may touch the move
+                    let column = match word.find('.') {
+                        Some(dot) if dot > 0 && word[..dot].chars().all(|c| c.is_ascii_digit()) => word[dot..].trim_start_matches('.'),
+                        _ => word.as_str(),
+                    };
+                    if column.is_empty() && word.contains('.') {
+                        continue;
+                    }
+                    match column.parse::<u8>() {
+                        Ok(col_num @ 1..=7) => record.moves.push(RecordedMove {col_num: col_num - 1, score: None, comment: None}),
+                        _ => return Err(self.error(&format!("expected a column 1-7, got {word:?}"))),
+                    }
+                }
+            }
+        }
+
+        record.replay().map_err(|message| self.error(&message))?;
+        Ok(record)
+    }
+}
+
+// Reads tag lines alone, e.g. headers to attach to a record being written
+pub fn parse_tags(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut parser = Parser {chars: text.char_indices().peekable(), text, line: 1};
+    let mut tags: Vec<(String, String)> = Vec::new();
+    parser.skip_whitespace();
+    while parser.chars.peek().is_some() {
+        let (name, value) = parser.tag()?;
+        if tags.iter().any(|(tag, _)| *tag == name) {
+            return Err(parser.error(&format!("tag {name} appears twice")));
+        }
+        tags.push((name, value));
+        parser.skip_whitespace();
+    }
+    Ok(tags)
+}
+
+// Reads one record. Anything after its result is an error.
+pub fn parse_record(text: &str) -> Result<GameRecord, String> {
+    let mut records = parse_records(text)?;
+    match records.len() {
+        1 => Ok(records.remove(0)),
+        0 => Err("no game record".to_string()),
+        count => Err(format!("expected one game record, found {count}")),
+    }
+}
+
+// Reads every record in an archive, each checked by replaying its moves
+pub fn parse_records(text: &str) -> Result<Vec<GameRecord>, String> {
+    let mut parser = Parser {chars: text.char_indices().peekable(), text, line: 1};
+    let mut records = Vec::new();
+    loop {
+        parser.skip_whitespace();
+        if parser.chars.peek().is_none() {
+            return Ok(records);
+        }
+        records.push(parser.record()?);
+    }
+}
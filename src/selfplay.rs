@@ -0,0 +1,243 @@
+use crate::game::*;
+use crate::engine::*;
+use crate::book::*;
+use crate::random::Rng;
+use crate::positions::*;
+use crate::evaluator::*;
+use crate::mlp::Mlp;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineConfig {
+    pub name: String,
+    pub tt_bits: usize,
+    pub use_book: bool,
+    // Soft cap on nodes per move. Columns are searched in MOVE_ORDER and once the budget is spent
+    // the best column found so far is played, so a single column's search can overshoot it.
+    pub node_budget: Option<u64>,
+    // Percentage of moves played at random instead of searched
+    pub handicap: u8,
+    // Plies of heuristic search in place of solving, ending in the model's or the threat count's estimates
+    pub depth: Option<u8>,
+    pub model: Option<Mlp>,
+}
+
+impl EngineConfig {
+    pub fn new(name: &str, tt_bits: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            tt_bits,
+            use_book: true,
+            node_budget: None,
+            handicap: 0,
+            depth: None,
+            model: None,
+        }
+    }
+
+    // Applies settings such as "tt=20,book=0,budget=100000,handicap=10" or "depth=6,model=net.c4nn". Unset keys
+    // are left alone.
+    pub fn apply(&mut self, spec: &str) -> Result<(), String> {
+        for setting in spec.split(',').filter(|setting| !setting.is_empty()) {
+            let (key, value) = setting.split_once('=').ok_or_else(|| format!("expected key=value, got {setting}"))?;
+            let invalid = || format!("invalid value for {key}: {value}");
+            match key {
+                "tt" => self.tt_bits = value.parse().ok().filter(|bits| (MIN_TT_BITS..=32).contains(bits)).ok_or_else(invalid)?,
+                "book" => self.use_book = value.parse::<u8>().map_err(|_| invalid())? != 0,
+                "budget" => self.node_budget = Some(value.parse().map_err(|_| invalid())?),
+                "handicap" => self.handicap = value.parse().ok().filter(|percent| *percent <= 100).ok_or_else(invalid)?,
+                "depth" => self.depth = Some(value.parse().ok().filter(|depth| (1..=42).contains(depth)).ok_or_else(invalid)?),
+                "model" => self.model = Some(Mlp::load(Path::new(value))?),
+                _ => return Err(format!("unknown engine setting {key}")),
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Player {
+    pub config: EngineConfig,
+    table: TranspositionTable,
+    evaluator: Box<dyn Evaluator>,
+    rng: Rng,
+    pub nodes: u64,
+    pub moves: u64,
+    pub time: Duration,
+}
+
+impl Player {
+    pub fn new(config: EngineConfig, seed: u64) -> Self {
+        let evaluator: Box<dyn Evaluator> = match &config.model {
+            Some(model) => Box::new(model.clone()),
+            None => Box::new(ThreatEvaluator),
+        };
+        Self {
+            table: TranspositionTable::new(config.tt_bits),
+            evaluator,
+            config,
+            rng: Rng::new(seed),
+            nodes: 0,
+            moves: 0,
+            time: Duration::ZERO,
+        }
+    }
+
+    pub fn choose_move(&mut self, game: &mut Game, book: &OpeningBook) -> u8 {
+        let start = Instant::now();
+        let col_num = if self.rng.below(100) < self.config.handicap as u64 {
+            random_move(game, &mut self.rng)
+        } else {
+            match (self.config.depth, self.config.node_budget) {
+                (Some(depth), _) => heuristic_best_move(game, self.evaluator.as_mut(), depth, &mut self.nodes).expect("game is over").0,
+                (None, Some(budget)) => budgeted_move(game, &mut self.table, book, &mut self.nodes, budget),
+                (None, None) => best_move(game, &mut self.table, book, &mut self.nodes).expect("game is over").0,
+            }
+        };
+        self.time += start.elapsed();
+        self.moves += 1;
+        col_num
+    }
+}
+
+fn random_move(game: &Game, rng: &mut Rng) -> u8 {
+    let playable: Vec<u8> = (0..COLS)
+        .filter(|col_num| game.get_board_playable() & (COLUMN_MASK << (8 * col_num)) != 0)
+        .collect();
+    playable[rng.below(playable.len() as u64) as usize]
+}
+
+pub fn budgeted_move(game: &mut Game, transposition_table: &mut TranspositionTable, book: &OpeningBook,
+        nodes: &mut u64, budget: u64) -> u8 {
+    let limit = *nodes + budget;
+    let mut best: Option<(u8, i8)> = None;
+    for col_num in MOVE_ORDER {
+        if best.is_some() && *nodes >= limit {
+            break;
+        }
+        if let (true, row_number) = game.make_move(col_num) {
+            let eval = -search(game, transposition_table, book, nodes);
+            game.unmake_move(col_num, row_number);
+            if best.is_none_or(|(_, best_eval)| eval > best_eval) {
+                best = Some((col_num, eval));
+            }
+        }
+    }
+    best.expect("game is over").0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchResult {
+    FirstEngineWin,
+    SecondEngineWin,
+    Draw,
+}
+
+pub struct MatchGame {
+    // Full game as 0-6 column digits, opening included
+    pub moves: String,
+    pub first_engine_starts: bool,
+    pub result: MatchResult,
+}
+
+pub struct MatchSummary {
+    pub games: Vec<MatchGame>,
+    pub players: [Player; 2],
+}
+
+impl MatchSummary {
+    pub fn count(&self, result: MatchResult) -> usize {
+        self.games.iter().filter(|game| game.result == result).count()
+    }
+
+    pub fn print_table(&self) {
+        let wins = [self.count(MatchResult::FirstEngineWin), self.count(MatchResult::SecondEngineWin)];
+        let draws = self.count(MatchResult::Draw);
+        let games = self.games.len().max(1) as f64;
+        println!("{:<20}{:>8}{:>8}{:>8}{:>10}{:>14}{:>14}", "engine", "wins", "losses", "draws", "score %", "ms/move", "nodes/move");
+        for (i, player) in self.players.iter().enumerate() {
+            let score = (wins[i] as f64 + draws as f64 / 2.0) / games * 100.0;
+            let moves = player.moves.max(1);
+            println!("{:<20}{:>8}{:>8}{:>8}{:>10.1}{:>14.3}{:>14}", player.config.name, wins[i], wins[1 - i], draws, score,
+                player.time.as_secs_f64() * 1000.0 / moves as f64, player.nodes / moves);
+        }
+    }
+}
+
+// Plays every opening twice, once with each engine moving first. Engines with book=0 search without `book`.
+pub fn play_match(configs: [EngineConfig; 2], openings: &[String], seed: u64, book: &OpeningBook) -> Result<MatchSummary, String> {
+    let empty_book = OpeningBook::from_bytes(&[]);
+    let [first, second] = configs;
+    let mut players = [Player::new(first, seed), Player::new(second, seed.wrapping_add(1))];
+    let mut games = Vec::new();
+
+    for opening in openings {
+        for first_engine_starts in [true, false] {
+            let mut game = opening_game(opening)?;
+            let mut moves = opening.to_string();
+
+            while game.game_status == GameStatus::InProgress {
+                // Player one is whoever is to move at the start of the game
+                let first_engine_to_move = game.player_one_turn == first_engine_starts;
+                let player = &mut players[if first_engine_to_move {0} else {1}];
+                let book = if player.config.use_book {book} else {&empty_book};
+                let col_num = player.choose_move(&mut game, book);
+                game.make_move(col_num);
+                moves.push_str(&col_num.to_string());
+            }
+
+            let result = match game.game_status {
+                GameStatus::Player1Win if first_engine_starts => MatchResult::FirstEngineWin,
+                GameStatus::Player2Win if !first_engine_starts => MatchResult::FirstEngineWin,
+                GameStatus::Draw => MatchResult::Draw,
+                _ => MatchResult::SecondEngineWin,
+            };
+            games.push(MatchGame {
+                moves,
+                first_engine_starts,
+                result,
+            });
+        }
+    }
+    Ok(MatchSummary {games, players})
+}
+
+// The position after an opening's 0-6 digit moves, which must leave the game in progress
+fn opening_game(opening: &str) -> Result<Game, String> {
+    let mut game = Game::new();
+    for c in opening.chars() {
+        match c.to_digit(10) {
+            Some(col_num) if col_num < COLS as u32 && game.make_move(col_num as u8).0 => (),
+            _ => return Err(format!("illegal move {c:?} in opening {opening}")),
+        }
+    }
+    if game.game_status != GameStatus::InProgress {
+        return Err(format!("opening {opening} ends the game"));
+    }
+    Ok(game)
+}
+
+// Openings from a file with one per line, ignoring blank lines
+pub fn read_openings(path: &Path) -> Result<Vec<String>, String> {
+    let contents = std::fs::read_to_string(path).map_err(|error| format!("couldn't read {}: {error}", path.display()))?;
+    let mut openings = Vec::new();
+    for (line_number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        opening_game(line).map_err(|message| format!("{}:{}: {message}", path.display(), line_number + 1))?;
+        openings.push(line.to_string());
+    }
+    Ok(openings)
+}
+
+// Seeded random openings of `plies` moves where nobody can win immediately and the player to move scores within
+// `balance` of a draw, so neither engine starts from a position that is easily won
+pub fn random_openings(count: usize, plies: i8, balance: i8, seed: u64, transposition_table: &mut TranspositionTable,
+        book: &OpeningBook, nodes: &mut u64) -> Vec<String> {
+    let options = PositionOptions {ply: plies, exclude_immediate_wins: true};
+    random_scored_positions(count, &options, -balance, balance, seed, transposition_table, book, nodes).into_iter()
+        .map(|(moves, _)| moves)
+        .collect()
+}
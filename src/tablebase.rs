@@ -0,0 +1,143 @@
+use crate::game::*;
+use crate::random::Rng;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+// File layout: the magic bytes, a byte with the largest number of empty squares covered, then one little endian
+// u64 per position sorted ascending. Each record holds the position key above a low byte with the score.
+const MAGIC: &[u8; 4] = b"C4TB";
+const HEADER_LEN: usize = 5;
+
+// Scores for positions near the end of the game, from the side to move's point of view and on the same scale as
+// search, so they encode win, draw or loss together with how many moves it takes.
+pub struct Tablebase {
+    pub max_empty: i8,
+    records: Box<[u64]>,
+}
+
+impl Tablebase {
+    pub fn empty() -> Self {
+        Self {max_empty: -1, records: Box::new([])}
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return Err("not a tablebase file".to_string());
+        }
+        let body = &bytes[HEADER_LEN..];
+        if !body.len().is_multiple_of(8) {
+            return Err(format!("truncated tablebase: {} bytes of records", body.len()));
+        }
+        let records: Box<[u64]> = body.chunks_exact(8)
+            .map(|record| u64::from_le_bytes(record.try_into().unwrap()))
+            .collect();
+        if records.windows(2).any(|pair| pair[0] >> 8 >= pair[1] >> 8) {
+            return Err("tablebase records aren't sorted".to_string());
+        }
+        Ok(Self {max_empty: bytes[4] as i8, records})
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|error| format!("couldn't read {}: {error}", path.display()))?;
+        Self::from_bytes(&bytes).map_err(|error| format!("{}: {error}", path.display()))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(self.max_empty as u8);
+        for record in self.records.iter() {
+            bytes.extend(record.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn probe(&self, player_squares: u64, played: u64) -> Option<i8> {
+        if self.is_empty() || 42 - played.count_ones() as i8 > self.max_empty {
+            return None;
+        }
+        let key = get_position_key(player_squares, played);
+        let index = self.records.binary_search_by_key(&key, |record| record >> 8).ok()?;
+        Some(self.records[index] as u8 as i8)
+    }
+
+    // Retrograde analysis over every position reachable from `roots` with at most `max_empty` empty squares.
+    // Positions are gathered one ply at a time and then scored from the last ply back, each from the scores
+    // of its children. Covering every position with even a few empty squares would take hundreds of billions
+    // of entries, so tables are built from sampled roots. `roots` are (side to move's stones, played) pairs.
+    // Search probes the table at max_empty empty squares and at the position it starts from, as deeper
+    // positions reached through a root that isn't in the table are rarely in it themselves.
+    pub fn generate(roots: &[(u64, u64)], max_empty: i8) -> Self {
+        let mut layers: Vec<HashSet<(u64, u64)>> = vec![HashSet::new(); max_empty as usize + 1];
+        for &(player_squares, played) in roots {
+            let empty = 42 - played.count_ones() as usize;
+            if empty <= max_empty as usize {
+                layers[empty].insert((player_squares, played));
+            }
+        }
+        for empty in (2..=max_empty as usize).rev() {
+            let children: Vec<(u64, u64)> = layers[empty].iter()
+                .flat_map(|&(player_squares, played)| {
+                    let winning_squares = get_winning_squares(player_squares, played);
+                    let playable = get_playable_squares(played) & !winning_squares;
+                    (0..COLS).map(move |col_num| playable & (COLUMN_MASK << (8 * col_num)))
+                        .filter(|&square| square != 0)
+                        .map(move |square| (played & !player_squares, played | square))
+                })
+                .collect();
+            layers[empty - 1].extend(children);
+        }
+
+        let mut scores: HashMap<u64, i8> = HashMap::new();
+        for (empty, layer) in layers.iter().enumerate().skip(1) {
+            for &(player_squares, played) in layer {
+                let moves_made = 42 - empty as i8;
+                let winning_squares = get_winning_squares(player_squares, played);
+                let playable = get_playable_squares(played);
+                let score = if winning_squares & playable != 0 {
+                    21 - moves_made/2
+                } else {
+                    (0..COLS).map(|col_num| playable & (COLUMN_MASK << (8 * col_num)))
+                        .filter(|&square| square != 0)
+                        .map(|square| if empty == 1 {
+                            0
+                        } else {
+                            -scores[&get_position_key(played & !player_squares, played | square)]
+                        })
+                        .max()
+                        .unwrap()
+                };
+                scores.insert(get_position_key(player_squares, played), score);
+            }
+        }
+
+        let mut records: Vec<u64> = scores.into_iter().map(|(key, score)| key << 8 | score as u8 as u64).collect();
+        records.sort_unstable();
+        Self {max_empty, records: records.into_boxed_slice()}
+    }
+}
+
+// Seeded random games played until `max_empty` squares are left, skipping games that are already over
+pub fn random_roots(count: usize, max_empty: i8, seed: u64) -> Vec<(u64, u64)> {
+    let mut rng = Rng::new(seed);
+    let mut roots = Vec::new();
+    while roots.len() < count {
+        let mut game = Game::new();
+        while game.moves_made < 42 - max_empty && game.game_status == GameStatus::InProgress {
+            game.make_move(rng.below(COLS as u64) as u8);
+        }
+        if game.game_status == GameStatus::InProgress {
+            let player_squares = if game.player_one_turn {game.board_set & game.board_p1} else {game.board_set & !game.board_p1};
+            roots.push((player_squares, game.board_set));
+        }
+    }
+    roots
+}
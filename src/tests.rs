@@ -0,0 +1,1222 @@
+use crate::game::*;
+use crate::engine::*;
+use crate::book::*;
+use crate::random::Rng;
+use crate::perft::*;
+use crate::ordering::{HistoryOrdering, MoveOrderer, MoveOrdering, TableMoveOrdering};
+use crate::endgame::*;
+use crate::tablebase::*;
+use crate::analysis::*;
+use crate::packed_book::pack;
+use crate::explorer::OpeningExplorer;
+use crate::record::*;
+use crate::annotate::*;
+use crate::puzzle::*;
+use crate::positions::*;
+use crate::training::*;
+use crate::evaluator::*;
+use crate::mlp::Mlp;
+use crate::selfplay::{EngineConfig, random_openings, read_openings};
+use crate::{c4engine, c4explain, c4record_export, c4record_import, c4threats, game_from_moves, parse_test_line, read_test_file, setup_game};
+
+// First positions of Pons' Test_L3_R1 set
+const PONS_SAMPLE: &str = "2252576253462244111563365343671351441 -1
+7422341735647741166133573473242566 1
+23163416124767223154467471272416755633 0
+65214673556155731566316327373221417 -1";
+
+// Plays random legal moves until `plies` have been made, stopping early if the game ends
+fn random_game(rng: &mut Rng, plies: i8) -> (Game, Vec<(u8, u8)>) {
+    let mut game = Game::new();
+    let mut played = Vec::new();
+    while game.moves_made < plies && game.game_status == GameStatus::InProgress {
+        let col_num = rng.below(COLS as u64) as u8;
+        if let (true, row_number) = game.make_move(col_num) {
+            played.push((col_num, row_number));
+        }
+    }
+    (game, played)
+}
+
+fn zobrist_from_scratch(game: &Game) -> u64 {
+    let mut hash = 0;
+    for col_num in 0..COLS {
+        for row_num in 0..ROWS {
+            match game.get_slot(col_num, row_num) {
+                Slot::Player1 => hash ^= ZOBRIST_TABLE[col_num as usize][row_num as usize][0],
+                Slot::Player2 => hash ^= ZOBRIST_TABLE[col_num as usize][row_num as usize][1],
+                Slot::Empty => (),
+            }
+        }
+    }
+    hash
+}
+
+fn naive_winning_squares(player_squares: u64, played: u64) -> u64 {
+    let mut winning_squares = 0;
+    let owned = |col: i32, row: i32| {
+        (0..COLS as i32).contains(&col) && (0..ROWS as i32).contains(&row) && get_bit(player_squares, col as u8, row as u8)
+    };
+    for col in 0..COLS as i32 {
+        for row in 0..ROWS as i32 {
+            if get_bit(played, col as u8, row as u8) {
+                continue;
+            }
+            for (d_col, d_row) in [(1, 0), (0, 1), (1, 1), (1, -1)] {
+                let run = |sign: i32| (1..4).take_while(|&i| owned(col + sign * i * d_col, row + sign * i * d_row)).count();
+                if run(1) + run(-1) >= 3 {
+                    winning_squares = set_bit(winning_squares, col as u8, row as u8, true);
+                }
+            }
+        }
+    }
+    winning_squares
+}
+
+fn solve_test_line(line: &str, table: &mut TranspositionTable, book: &OpeningBook) {
+    let (moves, expected) = parse_test_line(line).expect("malformed test line");
+    let mut game = Game::new();
+    for col_num in moves {
+        assert!(game.make_move(col_num).0, "illegal move in {line}");
+    }
+    let mut nodes = 0;
+    assert_eq!(Some(search(&mut game, table, book, &mut nodes)), expected, "{line}");
+}
+
+// Builds book records for the given positions, sorted by code as in bookDeepDist.dat
+fn book_bytes(entries: &[(u64, u64, i8)]) -> Vec<u8> {
+    let mut records: Vec<(i32, i8)> = entries.iter()
+        .map(|&(board_set, board_p1, raw)| (huffman_code(board_set, board_p1, false), raw))
+        .collect();
+    records.sort();
+    records.iter().flat_map(|(code, raw)| {
+        let mut record = code.to_be_bytes().to_vec();
+        record.push(*raw as u8);
+        record
+    }).collect()
+}
+
+#[test]
+fn huffman_code_round_trips() {
+    let mut rng = Rng::new(1);
+    for _ in 0..2000 {
+        let (game, _) = random_game(&mut rng, 12);
+        if game.moves_made != 12 {
+            continue;
+        }
+        let code = huffman_code(game.board_set, game.board_p1, false);
+        assert_eq!(decode(code), (game.board_set, game.board_set & game.board_p1));
+        assert_eq!(huffman_code(game.board_set, game.board_p1, false), code);
+    }
+}
+
+#[test]
+fn huffman_code_reverse_is_mirror() {
+    let mut game = Game::new();
+    let mut mirrored = Game::new();
+    for col_num in [3, 0, 1, 6, 2, 2] {
+        game.make_move(col_num);
+        mirrored.make_move(COLS - 1 - col_num);
+    }
+    assert_eq!(huffman_code(game.board_set, game.board_p1, true),
+        huffman_code(mirrored.board_set, mirrored.board_p1, false));
+}
+
+#[test]
+fn winning_squares_match_naive_scan() {
+    let mut rng = Rng::new(2);
+    for _ in 0..5000 {
+        // Random column heights with random owners. Stones can't float so the bitboard only looks down columns.
+        let mut played = 0;
+        for col_num in 0..COLS {
+            played |= ((1 << rng.below(ROWS as u64 + 1)) - 1) << (8 * col_num);
+        }
+        let player_squares = played & rng.next_u64();
+        assert_eq!(get_winning_squares(player_squares, played), naive_winning_squares(player_squares, played),
+            "played={played:#x} player={player_squares:#x}");
+    }
+    for plies in 0..42 {
+        let (game, _) = random_game(&mut rng, plies);
+        let p1 = game.board_set & game.board_p1;
+        assert_eq!(get_winning_squares(p1, game.board_set), naive_winning_squares(p1, game.board_set));
+    }
+}
+
+#[test]
+fn make_unmake_restores_hash() {
+    let mut rng = Rng::new(3);
+    for _ in 0..200 {
+        let (mut game, played) = random_game(&mut rng, 42);
+        assert_eq!(game.get_hash(), zobrist_from_scratch(&game));
+        for (col_num, row_number) in played.into_iter().rev() {
+            assert!(game.unmake_move(col_num, row_number));
+            assert_eq!(game.get_hash(), zobrist_from_scratch(&game));
+        }
+        assert_eq!((game.board_set, game.get_hash(), game.moves_made), (0, 0, 0));
+        assert!(game.player_one_turn);
+    }
+}
+
+#[test]
+fn transpositions_share_a_hash() {
+    let mut game = Game::new();
+    let mut transposed = Game::new();
+    for (col_num, transposed_col) in [(3, 2), (4, 4), (2, 3)] {
+        game.make_move(col_num);
+        transposed.make_move(transposed_col);
+    }
+    assert_eq!(game.get_hash(), transposed.get_hash());
+}
+
+#[test]
+fn solves_pons_sample() {
+    let mut table = TranspositionTable::new(20);
+    let book = OpeningBook::new();
+    for line in PONS_SAMPLE.lines() {
+        solve_test_line(line, &mut table, &book);
+    }
+}
+
+#[test]
+fn solves_fixture_sets() {
+    let mut table = TranspositionTable::new(20);
+    let book = OpeningBook::new();
+    let fixtures = [
+        include_str!("../test_cases/fixtures/Test_L3_R1"),
+        include_str!("../test_cases/fixtures/Test_L2_R1"),
+        include_str!("../test_cases/fixtures/Test_L1_R1"),
+    ];
+    for line in fixtures.iter().flat_map(|fixture| fixture.lines().take(10)) {
+        solve_test_line(line, &mut table, &book);
+    }
+}
+
+#[test]
+fn principal_variation_reaches_the_score() {
+    let mut table = TranspositionTable::new(20);
+    let book = OpeningBook::new();
+    let mut nodes = 0;
+    for line in PONS_SAMPLE.lines() {
+        let (moves, expected) = parse_test_line(line).unwrap();
+        let mut game = Game::new();
+        for col_num in moves {
+            game.make_move(col_num);
+        }
+        let player_one_to_move = game.player_one_turn;
+        let moves_made = game.moves_made;
+        for col_num in principal_variation(&mut game, &mut table, &book, &mut nodes) {
+            assert!(game.make_move(col_num).0);
+        }
+        let expected = expected.unwrap();
+        match game.game_status {
+            GameStatus::Draw => assert_eq!(expected, 0),
+            GameStatus::Player1Win | GameStatus::Player2Win => {
+                let mover_won = (game.game_status == GameStatus::Player1Win) == player_one_to_move;
+                assert_eq!(mover_won, expected > 0, "{line}");
+                let winner_stones = (game.moves_made + 1) / 2;
+                assert_eq!(22 - winner_stones, expected.abs(), "{line} from ply {moves_made}");
+            }
+            GameStatus::InProgress => panic!("principal variation stopped early for {line}"),
+        }
+    }
+}
+
+#[test]
+fn principal_variation_follows_the_tie_break() {
+    let mut table = TranspositionTable::new(20);
+    let book = OpeningBook::new();
+    let mut nodes = 0;
+    let mut rng = Rng::new(11);
+    let mut lines_differ = false;
+    for _ in 0..12 {
+        let (mut game, _) = random_game(&mut rng, 20);
+        if game.game_status != GameStatus::InProgress {
+            continue;
+        }
+        let mut lines = Vec::new();
+        for tie_break in TieBreak::ALL {
+            for fastest_win in [true, false] {
+                let options = PvOptions {tie_break, fastest_win};
+                let line = principal_variation_with(&mut game, &mut table, &book, &mut nodes, options);
+                // Every move of the line is one the tie-break allows among those keeping the score or result
+                let mut played = Vec::new();
+                for &col_num in &line {
+                    let scores = column_scores(&mut game, &mut table, &book, &mut nodes);
+                    let score = scores.iter().flatten().copied().max().unwrap();
+                    let accepted = |col_num: u8| scores[col_num as usize]
+                        .is_some_and(|eval| if fastest_win {eval == score} else {eval.signum() == score.signum()});
+                    assert!(accepted(col_num), "{tie_break:?} played {col_num} with scores {scores:?}");
+                    let expected = match tie_break {
+                        TieBreak::TableMove => None,
+                        TieBreak::CentreFirst => MOVE_ORDER.into_iter().find(|&col_num| accepted(col_num)),
+                        TieBreak::LeftFirst => (0..COLS).find(|&col_num| accepted(col_num)),
+                    };
+                    assert!(expected.is_none_or(|expected| expected == col_num), "{tie_break:?} played {col_num} with scores {scores:?}");
+                    played.push((col_num, game.make_move(col_num).1));
+                }
+                assert_ne!(game.game_status, GameStatus::InProgress);
+                for (col_num, row_number) in played.into_iter().rev() {
+                    game.unmake_move(col_num, row_number);
+                }
+                lines.push(line);
+            }
+        }
+        lines_differ |= lines.iter().any(|line| *line != lines[0]);
+    }
+    assert!(lines_differ);
+}
+
+#[test]
+fn book_lookup_finds_entries_and_mirrors() {
+    let mut rng = Rng::new(4);
+    let mut entries = Vec::new();
+    while entries.len() < 50 {
+        let (game, _) = random_game(&mut rng, 12);
+        if game.moves_made == 12 && game.game_status == GameStatus::InProgress {
+            entries.push((game.board_set, game.board_p1, 100 - 2 * entries.len() as i8));
+        }
+    }
+    let book = OpeningBook::from_bytes(&book_bytes(&entries));
+    for (i, &(board_set, board_p1, _)) in entries.iter().enumerate() {
+        assert_eq!(book.lookup(board_set, board_p1), Some(15 - i as i8));
+
+        assert!(book.lookup(mirror_board(board_set), mirror_board(board_p1)).is_some());
+    }
+}
+
+#[test]
+fn book_lookup_misses_cleanly() {
+    let game = {
+        let mut game = Game::new();
+        for col_num in [3, 3, 3, 3, 3, 3, 2, 2, 2, 2, 2, 2] {
+            game.make_move(col_num);
+        }
+        game
+    };
+    assert_eq!(OpeningBook::new().lookup(game.board_set, game.board_p1), None);
+
+    // Codes either side of every entry, including below the first one
+    let book = OpeningBook::from_bytes(&book_bytes(&[(0x0101, 0x0001, 0)]));
+    assert_eq!(book.lookup(0x0001, 0x0001), None);
+    assert_eq!(book.lookup(0x0100_0000, 0), None);
+}
+
+#[test]
+fn book_applies_corrections() {
+    let mut records = Vec::new();
+    for code in [-689592004_i32, 1599634104, 2101158888] {
+        records.extend(code.to_be_bytes());
+        records.push(0);
+    }
+    let book = OpeningBook::from_bytes(&records);
+    let evals: Vec<i8> = book.entries().map(|(_, eval)| eval).collect();
+    assert_eq!(evals, [7, 2, 4]);
+}
+
+// The wasm exports share one static table so they are exercised from a single test
+#[test]
+fn c4engine_rejects_bad_positions() {
+    assert_eq!(c4engine("7"), i8::MIN);
+    assert_eq!(c4engine("3333333"), i8::MIN);
+    // Player one has already won so any further move is illegal
+    assert_eq!(c4engine("01010103"), i8::MIN);
+    assert!(c4threats("9").is_empty());
+    assert_eq!(c4explain("0000000"), "null");
+    let mut game = game_from_moves(MUST_BLOCK).unwrap();
+    let explanations = explain_moves(&mut game, &mut TranspositionTable::new(20), &OpeningBook::new(), &mut 0, 8);
+    let explanations: Vec<String> = explanations.iter().map(|explanation| explanation.to_json()).collect();
+    assert_eq!(c4explain(MUST_BLOCK), format!("[{}]", explanations.join(",")));
+
+    // Player one has four in a row so player two to move has lost
+    assert_eq!(c4engine("0101010"), -18);
+}
+
+// Player one to move must block player two's only threat, which every other column lets player two complete at once
+const MUST_BLOCK: &str = "61651632524034611221226450";
+
+#[test]
+fn explanations_match_the_solver() {
+    let mut game = game_from_moves(MUST_BLOCK).unwrap();
+    let opponent = if game.player_one_turn {game.board_set & !game.board_p1} else {game.board_set & game.board_p1};
+    let threats = get_winning_squares(opponent, game.board_set) & game.get_board_playable();
+    assert!(threats.count_ones() == 1 && game.get_winning_move().is_none());
+
+    let mut table = TranspositionTable::new(20);
+    let book = OpeningBook::new();
+    let mut nodes = 0;
+    let scores = column_scores(&mut game, &mut table, &book, &mut nodes);
+    let explanations = explain_moves(&mut game, &mut table, &book, &mut nodes, 42);
+    assert_eq!(explanations.iter().map(|explanation| explanation.col_num).collect::<Vec<u8>>(),
+        (0..COLS).filter(|&col_num| scores[col_num as usize].is_some()).collect::<Vec<u8>>());
+    let player_one_to_move = game.player_one_turn;
+    for explanation in &explanations {
+        assert_eq!(Some(explanation.score), scores[explanation.col_num as usize]);
+        assert_eq!(explanation.refutation, explanation.line.first().copied());
+
+        let mut played = vec![(explanation.col_num, game.make_move(explanation.col_num).1)];
+        // Leaving the threat open is refuted by completing it
+        if threats & (COLUMN_MASK << (8 * explanation.col_num)) == 0 {
+            let winning = get_winning_squares(opponent, game.board_set) & game.get_board_playable();
+            assert!(explanation.refutation.is_some_and(|col_num| winning & (COLUMN_MASK << (8 * col_num)) != 0));
+            assert_eq!(explanation.line.len(), 1);
+        }
+        for &col_num in &explanation.line {
+            let (legal, row_number) = game.make_move(col_num);
+            assert!(legal, "column {} line {:?}", explanation.col_num, explanation.line);
+            played.push((col_num, row_number));
+        }
+        // The line runs to the end of the game with the result and length the score gives
+        match game.game_status {
+            GameStatus::Draw => assert_eq!(explanation.score, 0),
+            GameStatus::Player1Win | GameStatus::Player2Win => {
+                let mover_won = (game.game_status == GameStatus::Player1Win) == player_one_to_move;
+                assert_eq!(mover_won, explanation.score > 0);
+                assert_eq!(22 - (game.moves_made + 1) / 2, explanation.score.abs());
+            }
+            GameStatus::InProgress => panic!("line for column {} stopped early", explanation.col_num),
+        }
+        for (col_num, row_number) in played.into_iter().rev() {
+            game.unmake_move(col_num, row_number);
+        }
+    }
+    // Blocking is the only move that doesn't lose at once
+    let block = (threats.trailing_zeros() / 8) as u8;
+    let best = explanations.iter().max_by_key(|explanation| explanation.score).unwrap();
+    assert_eq!(best.col_num, block);
+    assert!(explanations.iter().all(|explanation| explanation.col_num == block || explanation.score == -(22 - (game.moves_made + 2) / 2)));
+}
+
+// Bit of the square at col_num, row_num, counting rows from 0 at the bottom
+fn square(col_num: u8, row_num: u8) -> u64 {
+    1 << (8 * col_num + row_num)
+}
+
+#[test]
+fn threat_analysis_classifies_known_positions() {
+    // Player one holds the bottom of columns 0 to 2 and player two the row above, so player one threatens the
+    // bottom of column 3 right away and player two the square on top of it
+    let analysis = threat_analysis(&game_from_moves("001122").unwrap());
+    assert_eq!(analysis.player1, Threats {
+        all: square(3, 0),
+        immediate: square(3, 0),
+        odd: square(3, 0),
+        even: 0,
+        poisoned: square(3, 0),
+        zugzwang: square(3, 0),
+    });
+    assert_eq!(analysis.player2, Threats {
+        all: square(3, 1),
+        immediate: 0,
+        odd: 0,
+        even: square(3, 1),
+        poisoned: 0,
+        zugzwang: square(3, 1),
+    });
+    assert_eq!(analysis.shared, 0);
+
+    // Cells are indexed column * ROWS + row
+    let mut cells = vec![0; 42];
+    cells[3 * 6] = CELL_P1_THREAT | CELL_P1_IMMEDIATE | CELL_P1_POISONED;
+    cells[3 * 6 + 1] = CELL_P2_THREAT;
+    assert_eq!(analysis.cell_flags(), cells);
+
+    // Both players need the bottom of column 3 to finish their row
+    let analysis = threat_analysis(&game_from_moves("041526").unwrap());
+    assert_eq!(analysis.shared, square(3, 0));
+    assert_eq!(analysis.player1.immediate, square(3, 0));
+    assert_eq!(analysis.player2.immediate, square(3, 0));
+    // A threat on the bottom row is on an odd row, which only helps player one in zugzwang
+    assert_eq!(analysis.player1.zugzwang, square(3, 0));
+    assert_eq!(analysis.player2.zugzwang, 0);
+    assert_eq!(analysis.cell_flags()[3 * 6], CELL_P1_THREAT | CELL_P1_IMMEDIATE | CELL_P2_THREAT | CELL_P2_IMMEDIATE);
+
+    // Player two's second row stones in columns 1 to 3 threaten both ends, one of them already playable
+    let analysis = threat_analysis(&game_from_moves("11223350").unwrap());
+    assert_eq!(analysis.player2.all, square(0, 1) | square(4, 1));
+    assert_eq!(analysis.player2.even, square(0, 1) | square(4, 1));
+    assert_eq!(analysis.player2.immediate, square(0, 1));
+    assert_eq!(analysis.player2.zugzwang, square(0, 1) | square(4, 1));
+    // Player one's own threat sits beneath one of them
+    assert_eq!(analysis.player1.all, square(4, 0));
+    assert_eq!(analysis.player1.poisoned, square(4, 0));
+    assert_eq!(analysis.player2.poisoned, 0);
+}
+
+#[test]
+fn parse_test_line_reports_errors() {
+    assert_eq!(parse_test_line("4455 -2"), Ok((vec![3, 3, 4, 4], Some(-2))));
+    assert_eq!(parse_test_line("17"), Ok((vec![0, 6], None)));
+    assert!(parse_test_line("").is_err());
+    assert!(parse_test_line("08").is_err());
+    assert!(parse_test_line("12 x").is_err());
+    assert!(parse_test_line("12 3 4").is_err());
+
+    // A seventh stone in a column is reported rather than panicking
+    let (moves, _) = parse_test_line("1111111").unwrap();
+    assert_eq!(setup_game(&mut Game::new(), &moves), Err("illegal move 1 after 6 moves".to_string()));
+    assert!(read_test_file("test_cases/missing").is_err());
+}
+
+#[test]
+fn perft_matches_published_counts() {
+    let stats = perft(7);
+    for ply_stats in &stats {
+        assert_eq!(ply_stats.matches_published(), Some(true), "{ply_stats:?}");
+    }
+    // Player one can first connect four on their fourth move, ply 7
+    assert_eq!(stats[6].player1_wins, 0);
+    assert!(stats[7].player1_wins > 0);
+    assert_eq!(stats[7].player2_wins, 0);
+    // The opening move and its mirror image leave 4 distinct positions
+    assert_eq!(stats[1].mirror_positions, 4);
+}
+
+#[test]
+fn move_orderings_agree() {
+    let book = OpeningBook::new();
+    for ordering in MoveOrdering::ALL {
+        let mut table = TranspositionTable::new(20);
+        for line in PONS_SAMPLE.lines() {
+            let (moves, expected) = parse_test_line(line).unwrap();
+            let mut game = Game::new();
+            for col_num in moves {
+                game.make_move(col_num);
+            }
+            let mut nodes = 0;
+            assert_eq!(Some(search_ordered(&mut game, &mut table, &book, ordering, ENDGAME_EMPTY_SQUARES, &mut nodes)), expected,
+                "{} on {line}", ordering.name());
+        }
+    }
+}
+
+#[test]
+fn saturated_history_still_orders_every_column() {
+    let mut ordering = HistoryOrdering::default();
+    let mut game = Game::new();
+    // Each cutoff at the root adds 42 * 42 until the entry saturates. The centre column is scored first.
+    for _ in 0..=u32::MAX / (42 * 42) {
+        ordering.record_cutoff(&game, 3);
+    }
+    let order = ordering.order_moves(&mut game, None);
+    assert_eq!(order[0], 3);
+    let mut sorted = order;
+    sorted.sort_unstable();
+    assert_eq!(sorted, [0, 1, 2, 3, 4, 5, 6]);
+}
+
+#[test]
+fn non_losing_moves_never_allow_an_immediate_win() {
+    let mut rng = Rng::new(5);
+    for _ in 0..500 {
+        let plies = rng.below(40) as i8;
+        let (mut game, _) = random_game(&mut rng, plies);
+        if game.game_status != GameStatus::InProgress || game.get_winning_move().is_some() {
+            continue;
+        }
+        let non_losing_moves = game.possible_non_losing_moves();
+        for col_num in 0..COLS {
+            if let (true, row_number) = game.make_move(col_num) {
+                let loses = game.game_status == GameStatus::InProgress && game.get_winning_move().is_some();
+                game.unmake_move(col_num, row_number);
+                assert_eq!(non_losing_moves & (COLUMN_MASK << (8 * col_num)) != 0, !loses, "column {col_num}");
+            }
+        }
+    }
+}
+
+#[test]
+fn endgame_solver_matches_full_search() {
+    let mut rng = Rng::new(6);
+    let book = OpeningBook::new();
+    let mut table = TranspositionTable::new(16);
+    let mut checked = 0;
+    while checked < 200 {
+        let (mut game, _) = random_game(&mut rng, 30);
+        if game.game_status != GameStatus::InProgress {
+            continue;
+        }
+        let player_squares = if game.player_one_turn {game.board_set & game.board_p1} else {game.board_set & !game.board_p1};
+        let mut nodes = 0;
+        let expected = search_ordered(&mut game, &mut table, &book, MoveOrdering::TableMove, 0, &mut nodes);
+        assert_eq!(solve_endgame(player_squares, game.board_set, -22, 22, &Tablebase::empty(), &mut nodes), expected);
+        checked += 1;
+    }
+}
+
+// Appends every position `plies` moves below game that is still in progress, as (side to move's stones, played)
+fn collect_positions(game: &mut Game, plies: u8, positions: &mut Vec<(u64, u64)>) {
+    if game.game_status != GameStatus::InProgress {
+        return;
+    }
+    if plies == 0 {
+        let player_squares = if game.player_one_turn {game.board_set & game.board_p1} else {game.board_set & !game.board_p1};
+        positions.push((player_squares, game.board_set));
+        return;
+    }
+    for col_num in 0..COLS {
+        if let (true, row_number) = game.make_move(col_num) {
+            collect_positions(game, plies - 1, positions);
+            game.unmake_move(col_num, row_number);
+        }
+    }
+}
+
+#[test]
+fn tablebase_matches_endgame_solver() {
+    let roots = random_roots(30, 8, 7);
+    let tablebase = Tablebase::from_bytes(&Tablebase::generate(&roots, 8).to_bytes()).unwrap();
+    assert!(tablebase.len() > roots.len());
+    let mut nodes = 0;
+    for &(player_squares, played) in &roots {
+        assert_eq!(tablebase.probe(player_squares, played), Some(solve_endgame(player_squares, played, -22, 22, &Tablebase::empty(), &mut nodes)));
+    }
+    // Every position a game from a root passes through is covered too, until someone can win at once
+    let mut rng = Rng::new(7);
+    for &(mut player_squares, mut played) in &roots {
+        loop {
+            let playable = get_playable_squares(played);
+            if playable == 0 || get_winning_squares(player_squares, played) & playable != 0 {
+                break;
+            }
+            let squares: Vec<u64> = (0..COLS).map(|col_num| playable & (COLUMN_MASK << (8 * col_num))).filter(|&square| square != 0).collect();
+            (player_squares, played) = (played & !player_squares, played | squares[rng.below(squares.len() as u64) as usize]);
+            if played.count_ones() < 42 {
+                assert_eq!(tablebase.probe(player_squares, played), Some(solve_endgame(player_squares, played, -22, 22, &Tablebase::empty(), &mut nodes)));
+            }
+        }
+    }
+    // Positions outside the table or with too many empty squares aren't found
+    assert_eq!(tablebase.probe(0, 0), None);
+    for root in random_roots(10, 8, 99).into_iter().filter(|root| !roots.contains(root)) {
+        assert_eq!(tablebase.probe(root.0, root.1), None);
+    }
+    assert!(Tablebase::from_bytes(b"C4TB\x08\x01").is_err());
+
+    // A table of everything 4 moves below a position lets a search from there stop at that depth, and a search
+    // from one of the table's positions stop at once
+    let (mut game, _) = loop {
+        let (game, played) = random_game(&mut rng, 30);
+        if game.moves_made == 30 && game.game_status == GameStatus::InProgress && game.get_winning_move().is_none() {
+            break (game, played);
+        }
+    };
+    let mut roots = Vec::new();
+    collect_positions(&mut game, 4, &mut roots);
+    let tablebase = Tablebase::generate(&roots, 8);
+    let with_table = OpeningBook::new().with_tablebase(tablebase);
+    let without_table = OpeningBook::new();
+    let (mut nodes_with, mut nodes_without) = (0, 0);
+    let score = search_with(&mut game, &mut TranspositionTable::new(16), &with_table, &mut TableMoveOrdering, 4, &mut nodes_with);
+    assert_eq!(search_with(&mut game, &mut TranspositionTable::new(16), &without_table, &mut TableMoveOrdering, 4, &mut nodes_without), score);
+    assert!(nodes_with < nodes_without, "{nodes_with} nodes with the table, {nodes_without} without");
+
+    // The default search hands the same position to solve_endgame, which probes the table at its roots' depth
+    let (mut nodes_with, mut nodes_without) = (0, 0);
+    let score = search(&mut game, &mut TranspositionTable::new(16), &with_table, &mut nodes_with);
+    assert_eq!(search(&mut game, &mut TranspositionTable::new(16), &without_table, &mut nodes_without), score);
+    assert!(nodes_with < nodes_without, "{nodes_with} nodes with the table, {nodes_without} without");
+
+    // Five moves down is below the roots' depth, where only the search's own position is probed
+    while game.moves_made < 35 {
+        let col_num = (0..COLS).find(|&col_num| {
+            let (legal, row_number) = game.make_move(col_num);
+            let in_progress = legal && game.game_status == GameStatus::InProgress;
+            if legal {
+                game.unmake_move(col_num, row_number);
+            }
+            in_progress
+        }).unwrap();
+        game.make_move(col_num);
+    }
+    let mut nodes = 0;
+    let score = search(&mut game, &mut TranspositionTable::new(16), &with_table, &mut nodes);
+    assert_eq!(nodes, 1);
+    assert_eq!(search(&mut game, &mut TranspositionTable::new(16), &without_table, &mut nodes), score);
+}
+
+#[test]
+fn ply_books_are_searched_at_their_ply() {
+    let mut rng = Rng::new(8);
+    let (mut game, played) = loop {
+        let (game, played) = random_game(&mut rng, 14);
+        if game.moves_made == 14 && game.game_status == GameStatus::InProgress && game.get_winning_move().is_none() {
+            break (game, played);
+        }
+    };
+    let mut mirrored = Game::new();
+    for (col_num, _) in played {
+        mirrored.make_move(COLS - 1 - col_num);
+    }
+    let player_squares = game.board_set & game.board_p1;
+    // A deliberately wrong score shows the book answered rather than the search
+    let ply_book = PlyBook::from_bytes(&PlyBook::from_entries(14, &[(player_squares, game.board_set, 9)]).to_bytes()).unwrap();
+    let book = OpeningBook::new().with_ply_book(ply_book);
+    assert!(book.covers(14) && !book.covers(16));
+    assert_eq!(book.lookup_position(mirrored.board_set, mirrored.board_p1), Some(BookHit {eval: 9, ply: 14}));
+
+    let mut table = TranspositionTable::new(16);
+    let mut nodes = 0;
+    assert_eq!(search(&mut game, &mut table, &book, &mut nodes), 9);
+    assert_eq!(nodes, 1);
+}
+
+#[test]
+fn packed_book_matches_unpacked_records() {
+    let mut rng = Rng::new(9);
+    let mut entries = Vec::new();
+    while entries.len() < 3000 {
+        let (game, _) = random_game(&mut rng, 12);
+        if game.moves_made == 12 {
+            entries.push((game.board_set, game.board_p1, rng.below(100) as i8 - 50));
+        }
+    }
+    let bytes = book_bytes(&entries);
+    // The same position can come up twice but the book holds each code once
+    let mut records: Vec<(i32, i8)> = bytes.chunks_exact(5)
+        .map(|record| (i32::from_be_bytes(record[..4].try_into().unwrap()), record[4] as i8))
+        .collect();
+    records.dedup_by_key(|record| record.0);
+    let unique: Vec<u8> = records.iter().flat_map(|(code, raw)| [&code.to_be_bytes()[..], &[*raw as u8]].concat()).collect();
+    let book = OpeningBook::from_bytes(&unique);
+
+    let unpacked: Vec<(i32, i8)> = records.iter().map(|&(code, raw)| (code, book_eval(code, raw))).collect();
+    assert_eq!(book.entries().collect::<Vec<_>>(), unpacked);
+    assert!(book.packed_size() < unique.len());
+    let find_unpacked = |code: i32| unpacked.binary_search_by_key(&code, |entry| entry.0).ok().map(|i| unpacked[i].1);
+    for &(code, _) in &unpacked {
+        for code in [code, code.wrapping_sub(1), code.wrapping_add(1), code ^ i32::MIN] {
+            assert_eq!(book.find(code), find_unpacked(code), "{code}");
+        }
+    }
+    assert_eq!(book.find(i32::MIN), find_unpacked(i32::MIN));
+    assert_eq!(book.find(i32::MAX), find_unpacked(i32::MAX));
+}
+
+#[test]
+fn mapped_book_matches_book_in_memory() {
+    let mut rng = Rng::new(10);
+    let mut entries = Vec::new();
+    while entries.len() < 500 {
+        let (game, _) = random_game(&mut rng, 12);
+        if game.moves_made == 12 {
+            entries.push((game.board_set, game.board_p1, rng.below(100) as i8 - 50));
+        }
+    }
+    let mut records: Vec<[u8; 5]> = book_bytes(&entries).chunks_exact(5).map(|record| record.try_into().unwrap()).collect();
+    records.dedup_by_key(|record| i32::from_be_bytes(record[..4].try_into().unwrap()));
+    let records = records.concat();
+    let path = std::env::temp_dir().join(format!("c4engine-mapped-book-{}.c4bk", std::process::id()));
+    std::fs::write(&path, pack(&records)).unwrap();
+    let mapped = OpeningBook::open(&path);
+    std::fs::remove_file(&path).unwrap();
+    let mapped = mapped.unwrap();
+
+    let book = OpeningBook::from_bytes(&records);
+    assert_eq!(mapped.len(), book.len());
+    assert_eq!(mapped.entries().collect::<Vec<_>>(), book.entries().collect::<Vec<_>>());
+    for &(board_set, board_p1, _) in &entries {
+        assert_eq!(mapped.lookup(board_set, board_p1), book.lookup(board_set, board_p1));
+        assert!(mapped.lookup(board_set, board_p1).is_some());
+    }
+    assert!(OpeningBook::open(&path).is_err());
+}
+
+#[test]
+fn book_entries_and_coverage_below_a_position() {
+    let mut rng = Rng::new(11);
+    let (mut game, _) = loop {
+        let (game, played) = random_game(&mut rng, 10);
+        if game.game_status == GameStatus::InProgress && game.get_winning_move().is_none() {
+            break (game, played);
+        }
+    };
+    // Ply 12 positions below the root by brute force, and the ones where the game ends on the way
+    let mut descendants = Vec::new();
+    let mut finished = Vec::new();
+    for first in 0..COLS {
+        let (true, first_row) = game.make_move(first) else { continue };
+        if game.game_status != GameStatus::InProgress {
+            finished.push((game.board_set, game.board_set & game.board_p1));
+        } else {
+            for second in 0..COLS {
+                let (true, second_row) = game.make_move(second) else { continue };
+                let position = (game.board_set, game.board_set & game.board_p1);
+                if game.game_status == GameStatus::InProgress {
+                    descendants.push(position);
+                } else {
+                    finished.push(position);
+                }
+                game.unmake_move(second, second_row);
+            }
+        }
+        game.unmake_move(first, first_row);
+    }
+    descendants.sort();
+    descendants.dedup();
+    finished.sort();
+    finished.dedup();
+
+    // The book holds all but one descendant, some stored as their mirror image
+    let stored: Vec<(u64, u64, i8)> = descendants[1..].iter().enumerate()
+        .map(|(i, &(played, p1_squares))| if i % 2 == 0 {
+            (played, p1_squares, 60)
+        } else {
+            (mirror_board(played), mirror_board(p1_squares), -60)
+        })
+        .collect();
+    let mut records: Vec<[u8; 5]> = book_bytes(&stored).chunks_exact(5).map(|record| record.try_into().unwrap()).collect();
+    records.dedup_by_key(|record| i32::from_be_bytes(record[..4].try_into().unwrap()));
+    let book = OpeningBook::from_bytes(&records.concat());
+
+    let coverage = book.coverage(game.board_set, game.board_p1).unwrap();
+    assert_eq!(coverage, BookCoverage {descendants: descendants.len() as u64, covered: descendants.len() as u64 - 1,
+        finished: finished.len() as u64});
+    assert!(!coverage.is_complete());
+
+    let entries: Vec<(i32, i8)> = book.entries().collect();
+    for (i, &(played, p1_squares)) in descendants[1..].iter().enumerate() {
+        let entry = book.lookup_entry(played, p1_squares).unwrap();
+        let symmetric = mirror_board(played) == played && mirror_board(p1_squares) == p1_squares;
+        assert_eq!(entry.mirrored, i % 2 == 1 && !symmetric);
+        assert_eq!(entry.code, huffman_code(played, p1_squares, entry.mirrored));
+        assert_eq!(entries[entry.index], (entry.code, entry.eval));
+        assert_eq!(entry.eval, book_eval(entry.code, entry.raw));
+        assert!(!entry.corrected);
+        assert_eq!(Some(entry.eval), book.lookup(played, p1_squares));
+    }
+
+    let past_book = game_from_moves("0123456012345").unwrap();
+    assert_eq!(book.coverage(past_book.board_set, past_book.board_p1), None);
+}
+
+#[test]
+fn explorer_matches_search_with_part_of_the_book_missing() {
+    let mut rng = Rng::new(12);
+    let mut table = TranspositionTable::new(20);
+    let no_book = OpeningBook::from_bytes(&[]);
+    let mut nodes = 0;
+    // Ply 12 positions below this one solve quickly
+    let mut game = game_from_moves("4252656554").unwrap();
+
+    // Every other ply 12 position below the root goes in the book with its searched score, some as their mirror
+    let mut stored = Vec::new();
+    for first in 0..COLS {
+        let (true, first_row) = game.make_move(first) else { continue };
+        for second in 0..COLS {
+            let (true, second_row) = game.make_move(second) else { continue };
+            if game.game_status == GameStatus::InProgress && rng.below(2) == 0 {
+                let eval = search(&mut game, &mut table, &no_book, &mut nodes);
+                let raw = match eval {
+                    0 => 0,
+                    eval if eval > 0 => 70 + 2 * eval,
+                    eval => -69 + 2 * eval,
+                };
+                let (set, p1) = (game.board_set, game.board_set & game.board_p1);
+                stored.push(if rng.below(2) == 0 {(set, p1, raw)} else {(mirror_board(set), mirror_board(p1), raw)});
+            }
+            game.unmake_move(second, second_row);
+        }
+        game.unmake_move(first, first_row);
+    }
+    let mut records: Vec<[u8; 5]> = book_bytes(&stored).chunks_exact(5).map(|record| record.try_into().unwrap()).collect();
+    records.dedup_by_key(|record| i32::from_be_bytes(record[..4].try_into().unwrap()));
+    let book = OpeningBook::from_bytes(&records.concat());
+
+    let mut explorer = OpeningExplorer::new();
+    let exploration = explorer.explore(&mut game, &mut table, &book, &mut nodes).unwrap();
+    let scores = column_scores(&mut game, &mut table, &no_book, &mut nodes);
+    let explored: Vec<(u8, i8)> = exploration.continuations.iter().map(|continuation| (continuation.col_num, continuation.score)).collect();
+    let expected: Vec<(u8, i8)> = (0..COLS).filter_map(|col_num| Some((col_num, scores[col_num as usize]?))).collect();
+    assert_eq!(explored, expected);
+    assert!(!exploration.fallbacks.is_empty());
+    for &code in &exploration.fallbacks {
+        let (set, p1) = decode(code);
+        assert_eq!(set.count_ones() as i8, BOOK_PLY);
+        assert_eq!(book.lookup(set, p1), None);
+    }
+
+    assert!(explorer.cached() > 0);
+    assert_eq!(explorer.explore(&mut game, &mut table, &book, &mut nodes), Some(exploration));
+    let mut past_book = game_from_moves("000000111111").unwrap();
+    assert_eq!(explorer.explore(&mut past_book, &mut table, &book, &mut nodes), None);
+}
+
+#[test]
+fn game_records_round_trip_and_are_validated() {
+    let mut record = GameRecord::from_moves(&[3, 3, 2, 4, 1, 5, 0]).unwrap();
+    assert_eq!(record.result, RecordResult::Player1Win);
+    record.set_header("Player1", "Alice \"the wall\"");
+    record.set_header("Player2", "c4engine");
+    record.moves[0].score = Some(1);
+    record.moves[0].comment = Some("centre {as usual} \\ fine".to_string());
+    record.moves[3].score = Some(0);
+    record.moves[4].score = Some(-18);
+    let text = record.to_text();
+    assert!(text.contains("1. 4 (+1) {centre {as usual\\} \\\\ fine} 4 2. 3 5 (0)"), "{text}");
+    assert_eq!(parse_record(&text), Ok(record.clone()));
+
+    // Several records, compact move numbers and no tags
+    let archive = format!("{text}\n1.4 4 2.4 4 *\n\n[Result \"0-1\"]\n1. 4 4 2. 4 4 0-1\n");
+    let records = parse_records(&archive).unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[1].move_string(), "3333");
+    assert_eq!(records[1].result, RecordResult::Unfinished);
+    assert_eq!(records[2].result, RecordResult::Player2Win);
+    assert!(parse_record(&archive).is_err());
+
+    for bad in [
+        "1. 4 4 2. 8 *",
+        "1. 1 1 2. 1 1 3. 1 1 4. 1 *",
+        "1. 1 2 2. 1 2 3. 1 2 4. 1 2 *",
+        "1. 1 2 2. 1 2 3. 1 2 4. 1 0-1",
+        "[Result \"1-0\"]\n1. 4 *",
+        "[Board \"8x7\"]\n1. 4 *",
+        "1. 4 {unclosed *",
+        "(+1) 1. 4 *",
+        "1. 4 4",
+    ] {
+        assert!(parse_record(bad).is_err(), "{bad}");
+    }
+    assert!(parse_record("1. 1 2 2. 1 2 3. 1 2 4. 1 1-0").is_ok());
+
+    let exported = c4record_export("3324150", "[Player1 \"Alice\"]\n[Event \"club night\"]", &[1, i8::MIN, 0], "centre\n\nblocks");
+    let imported = c4record_import(&exported);
+    assert!(imported.starts_with("{\"headers\":{\"Ruleset\":\"standard\",\"Board\":\"7x6\",\"Result\":\"1-0\",\"Player1\":\"Alice\""), "{imported}");
+    assert!(imported.contains("\"moves\":\"3324150\",\"scores\":[1,null,0,null,null,null,null],\"comments\":[\"centre\",null,\"blocks\",null"), "{imported}");
+    assert_eq!(c4record_export("3333", "[Result \"1/2-1/2\"]", &[], ""), "[Ruleset \"standard\"]\n[Board \"7x6\"]\n[Result \"1/2-1/2\"]\n\n1. 4 4 2. 4 4 1/2-1/2\n");
+    assert_eq!(c4record_export("33333333", "", &[], ""), "");
+    assert!(c4record_import("1. 9 *").starts_with("{\"error\":"));
+    assert_eq!(json_string("a \"b\" \\ c\n\t\u{1}"), "\"a \\\"b\\\" \\\\ c\\n\\t\\u0001\"");
+}
+
+#[test]
+fn annotations_grade_moves_against_the_solver() {
+    let mut rng = Rng::new(13);
+    let mut table = TranspositionTable::new(20);
+    let book = OpeningBook::new();
+    let mut nodes = 0;
+    let first = 18;
+    let moves: Vec<u8> = loop {
+        let (game, played) = random_game(&mut rng, 42);
+        if game.game_status != GameStatus::InProgress && played.len() > first + 4 {
+            break played.iter().map(|&(col_num, _)| col_num).collect();
+        }
+    };
+
+    let annotations = annotate_moves(&moves, first, &mut table, &book, &mut nodes).unwrap();
+    assert_eq!(annotations.len(), moves.len() - first);
+    let mut game = game_from_moves(&moves[..first].iter().map(|col_num| col_num.to_string()).collect::<String>()).unwrap();
+    for (i, annotation) in annotations.iter().enumerate() {
+        assert_eq!(annotation.col_num, moves[first + i]);
+        assert_eq!(annotation.best_score, search(&mut game, &mut table, &book, &mut nodes));
+        let scores = column_scores(&mut game, &mut table, &book, &mut nodes);
+        let (_, best_eval) = best_move(&mut game, &mut table, &book, &mut nodes).unwrap();
+        assert_eq!(annotation.best_score, best_eval);
+        // A winning move ends the game so column_scores can't score it
+        if game.get_winning_move() != Some(annotation.col_num) {
+            assert_eq!(Some(annotation.score), scores[annotation.col_num as usize]);
+        }
+        let expected = if annotation.score == annotation.best_score {
+            MoveQuality::Best
+        } else if annotation.score.signum() == annotation.best_score.signum() {
+            MoveQuality::Inaccuracy
+        } else {
+            MoveQuality::Blunder
+        };
+        assert_eq!(annotation.quality, expected);
+        assert_eq!(annotation.best_move.is_none(), annotation.quality == MoveQuality::Best);
+        if let Some(best_move) = annotation.best_move {
+            assert!(game.get_winning_move() == Some(best_move) || scores[best_move as usize] == Some(annotation.best_score));
+        }
+        if let Some(next) = annotations.get(i + 1) {
+            assert_eq!(next.best_score, -annotation.score);
+        }
+        game.make_move(annotation.col_num);
+    }
+
+    let mut record = GameRecord::from_moves(&moves).unwrap();
+    assert_eq!(annotate_record(&mut record, first, &mut table, &book, &mut nodes), Ok(annotations.clone()));
+    assert!(record.moves[..first].iter().all(|recorded| recorded.score.is_none()));
+    for (recorded, annotation) in record.moves[first..].iter().zip(&annotations) {
+        assert_eq!(recorded.score, Some(annotation.score));
+        assert_eq!(recorded.comment.is_some(), annotation.quality != MoveQuality::Best);
+    }
+    assert_eq!(parse_record(&record.to_text()), Ok(record));
+
+    let mut illegal = moves.clone();
+    illegal.push(0);
+    assert!(annotate_moves(&illegal, moves.len(), &mut table, &book, &mut nodes).is_err());
+}
+
+#[test]
+fn puzzles_have_a_unique_win_in_n() {
+    let mut table = TranspositionTable::new(20);
+    let book = OpeningBook::new();
+    let mut nodes = 0;
+    let puzzles = generate_puzzles(6, 1, 3, PuzzleSource::Playouts, 4, &mut table, &book, &mut nodes).unwrap();
+    assert_eq!(puzzles.len(), 6);
+    assert_eq!(Ok(puzzles.clone()), generate_puzzles(6, 1, 3, PuzzleSource::Playouts, 4, &mut table, &book, &mut nodes));
+    for puzzle in &puzzles {
+        assert!((1..=3).contains(&puzzle.win_in));
+        assert_eq!(Puzzle::from_line(&puzzle.to_line()), Ok(puzzle.clone()));
+        let mut game = game_from_moves(&puzzle.moves).unwrap();
+        let score = search(&mut game, &mut table, &book, &mut nodes);
+        assert_eq!(moves_to_win(game.moves_made, score), Some(puzzle.win_in));
+        // No other column wins within 3 moves, even slower than the solution
+        let scores = column_scores(&mut game, &mut table, &book, &mut nodes);
+        let win_in = |col_score: &Option<i8>| col_score.and_then(|col_score| moves_to_win(game.moves_made, col_score));
+        assert_eq!(scores.iter().filter(|col_score| win_in(col_score).is_some_and(|win_in| win_in <= 3)).count(), 1);
+        assert_eq!(scores.iter().filter(|col_score| win_in(col_score).is_some()).count(), puzzle.alternatives as usize + 1);
+        assert_eq!(scores[puzzle.solution as usize], Some(score));
+
+        // The line ends with the solver's side completing four on its Nth move
+        let winner = if game.player_one_turn {GameStatus::Player1Win} else {GameStatus::Player2Win};
+        assert_eq!(puzzle.line.len(), 2 * puzzle.win_in as usize - 1);
+        assert_eq!(puzzle.line[0], puzzle.solution);
+        for &col_num in &puzzle.line {
+            assert!(game.make_move(col_num).0);
+        }
+        assert_eq!(game.game_status, winner);
+    }
+    assert!(Puzzle::from_line("0123 2 3 333 impossible 0 0").is_err());
+    let json = puzzles[0].to_json();
+    assert!(json.starts_with(&format!("{{\"moves\":\"{}\",\"win_in\":{},", puzzles[0].moves, puzzles[0].win_in)), "{json}");
+
+    // Ranges that can't be met fail rather than searching forever. Nothing past ply 12 takes more than 15 moves to win.
+    assert!(generate_puzzles(3, 2, 1, PuzzleSource::Playouts, 0, &mut table, &book, &mut nodes).is_err());
+    assert!(generate_puzzles(3, MAX_WIN_IN + 1, 21, PuzzleSource::Playouts, 0, &mut table, &book, &mut nodes).is_err());
+
+    // Book positions are turned back into moves that reach them
+    let mut rng = Rng::new(14);
+    let mut entries = Vec::new();
+    while entries.len() < 50 {
+        let (game, _) = random_game(&mut rng, BOOK_PLY);
+        if game.game_status == GameStatus::InProgress && game.moves_made == BOOK_PLY {
+            entries.push((game.board_set, game.board_p1, 0));
+        }
+    }
+    let mut records: Vec<[u8; 5]> = book_bytes(&entries).chunks_exact(5).map(|record| record.try_into().unwrap()).collect();
+    records.dedup_by_key(|record| i32::from_be_bytes(record[..4].try_into().unwrap()));
+    let small_book = OpeningBook::from_bytes(&records.concat());
+    for puzzle in generate_puzzles(3, 1, 2, PuzzleSource::Book, 5, &mut table, &small_book, &mut nodes).unwrap() {
+        let opening = game_from_moves(&puzzle.moves[..BOOK_PLY as usize]).unwrap();
+        assert!(small_book.lookup(opening.board_set, opening.board_p1).is_some());
+    }
+}
+
+#[test]
+fn random_positions_are_reproducible_and_filtered() {
+    let options = PositionOptions {ply: 10, exclude_immediate_wins: true};
+    let positions = random_positions(30, &options, 8);
+    assert_eq!(positions.len(), 30);
+    assert_eq!(positions, random_positions(30, &options, 8));
+    assert_ne!(positions, random_positions(30, &options, 9));
+    let mut seen = std::collections::HashSet::new();
+    for moves in &positions {
+        let game = game_from_moves(moves).unwrap();
+        assert_eq!(game.moves_made, 10);
+        assert_eq!(game.game_status, GameStatus::InProgress);
+        assert_eq!(game.get_winning_move(), None);
+        assert!(seen.insert((game.board_set, game.board_set & game.board_p1)));
+    }
+
+    // Without the filter some positions have a win on the board
+    let options = PositionOptions {ply: 20, exclude_immediate_wins: false};
+    assert!(random_positions(50, &options, 3).iter()
+        .any(|moves| game_from_moves(moves).unwrap().get_winning_move().is_some()));
+
+    // Only 7 positions exist after one move, and none before the first
+    assert_eq!(random_positions(10, &PositionOptions {ply: 1, ..Default::default()}, 0).len(), 7);
+    assert_eq!(random_positions(10, &PositionOptions {ply: 0, ..Default::default()}, 0), vec![String::new()]);
+
+    let book = OpeningBook::new();
+    let mut table = TranspositionTable::new(20);
+    let mut nodes = 0;
+    let options = PositionOptions {ply: 22, exclude_immediate_wins: true};
+    let scored = random_scored_positions(8, &options, 1, 5, 6, &mut table, &book, &mut nodes);
+    assert_eq!(scored.len(), 8);
+    for (moves, score) in &scored {
+        assert!((1..=5).contains(score));
+        assert_eq!(search(&mut game_from_moves(moves).unwrap(), &mut table, &book, &mut nodes), *score);
+    }
+}
+
+#[test]
+fn match_openings_are_balanced_and_checked() {
+    let book = OpeningBook::new();
+    let mut table = TranspositionTable::new(20);
+    let mut nodes = 0;
+    let openings = random_openings(4, 20, 1, 3, &mut table, &book, &mut nodes);
+    assert_eq!(openings.len(), 4);
+    for moves in &openings {
+        let score = search(&mut game_from_moves(moves).unwrap(), &mut table, &book, &mut nodes);
+        assert!((-1..=1).contains(&score), "{moves} scores {score}");
+    }
+
+    let path = std::env::temp_dir().join(format!("c4engine-openings-{}.txt", std::process::id()));
+    std::fs::write(&path, "3344\n\n 2 \n").unwrap();
+    assert_eq!(read_openings(&path), Ok(vec!["3344".to_string(), "2".to_string()]));
+    // Characters that aren't columns are reported with their line rather than skipped
+    for (contents, line, message) in [("33\n3a4\n", 2, "illegal move 'a' in opening 3a4"),
+            ("37\n", 1, "illegal move '7' in opening 37"), ("1\n0000000\n", 2, "illegal move '0' in opening 0000000"),
+            ("\n0101010\n", 2, "opening 0101010 ends the game")] {
+        std::fs::write(&path, contents).unwrap();
+        assert_eq!(read_openings(&path), Err(format!("{}:{line}: {message}", path.display())));
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn training_shards_are_reproducible_and_round_trip() {
+    assert_eq!(shard_sizes(250, 100), vec![100, 100, 50]);
+    assert!(shard_sizes(0, 100).is_empty());
+
+    let book = OpeningBook::new();
+    let mut table = TranspositionTable::new(20);
+    let mut nodes = 0;
+    let options = ExportOptions {min_ply: 20, max_ply: 26, shard_size: 12, seed: 5, ..Default::default()};
+    let shard = generate_shard(1, 12, &options, &mut table, &book, &mut nodes).unwrap();
+    assert_eq!(shard.len(), 12);
+    // Shards don't depend on the table's contents or on which shards came before
+    let mut fresh_table = TranspositionTable::new(16);
+    assert_eq!(shard, generate_shard(1, 12, &options, &mut fresh_table, &book, &mut nodes).unwrap());
+    assert_ne!(shard, generate_shard(0, 12, &options, &mut table, &book, &mut nodes).unwrap());
+
+    let self_play = ExportOptions {source: SampleSource::SelfPlay, ..options};
+    let samples: Vec<Sample> = shard.into_iter()
+        .chain(generate_shard(0, 12, &self_play, &mut table, &book, &mut nodes).unwrap())
+        .collect();
+    for sample in &samples {
+        assert!((20..=26).contains(&sample.moves_made));
+        assert_eq!(sample.moves_made as u32, sample.board_set.count_ones());
+        assert_eq!(sample.child_scores[sample.best_move as usize], Some(sample.score));
+        assert_eq!(sample.child_scores.iter().flatten().max(), Some(&sample.score));
+        let planes = sample.planes();
+        assert_eq!(planes.iter().map(|&square| square as u32).take(84).sum::<u32>(), sample.moves_made as u32);
+        assert_eq!(planes[84..126].iter().all(|&square| square == 1), sample.moves_made % 2 == 0);
+        // Full columns have nothing playable
+        for col_num in 0..7 {
+            let playable = (0..6).any(|row| planes[126 + row * 7 + col_num] == 1);
+            assert_eq!(playable, sample.child_scores[col_num].is_some());
+        }
+    }
+
+    let npz = to_npz(&samples);
+    assert_eq!(&npz[..4], b"PK\x03\x04");
+    assert_eq!(from_npz(&npz), Ok(samples.clone()));
+    assert_eq!(from_npz(&to_npz(&[])), Ok(Vec::new()));
+    let mut corrupt = npz.clone();
+    let last_data_byte = npz.windows(4).position(|window| window == b"PK\x01\x02").unwrap() - 1;
+    corrupt[last_data_byte] ^= 1;
+    assert!(from_npz(&corrupt).is_err());
+    assert!(from_npz(&npz[..npz.len() / 2]).is_err());
+
+    // An empty shard byte for byte: the first local header and file, with the CRC zlib gives for that file, and
+    // the end of the central directory. The npy header is padded to 128 bytes.
+    let empty = to_npz(&[]);
+    let planes = [b"\x93NUMPY\x01\x00\x76\x00".as_slice(), b"{'descr': '|u1', 'fortran_order': False, 'shape': (0, 4, 6, 7), }",
+        &[b' '; 52], b"\n"].concat();
+    let local_header = [b"PK\x03\x04\x14\x00\x00\x00\x00\x00\x00\x00\x21\x00".as_slice(), &0x7f31fd51u32.to_le_bytes(),
+        &128u32.to_le_bytes(), &128u32.to_le_bytes(), b"\x0a\x00\x00\x00planes.npy"].concat();
+    assert_eq!(&empty[..local_header.len() + 128], [local_header, planes].concat());
+    // Five entries, a 292 byte central directory starting at byte 852, no comment
+    assert_eq!(&empty[empty.len() - 22..], b"PK\x05\x06\x00\x00\x00\x00\x05\x00\x05\x00\x24\x01\x00\x00\x54\x03\x00\x00\x00\x00");
+    assert_eq!(empty.len(), 852 + 292 + 22);
+
+    // Book samples start from entries of the book and can't come before its ply
+    assert!(generate_shard(0, 1, &ExportOptions {source: SampleSource::Book, ..options}, &mut table, &book, &mut nodes).is_err());
+    let mut rng = Rng::new(21);
+    let mut entries = Vec::new();
+    while entries.len() < 20 {
+        let (game, _) = random_game(&mut rng, BOOK_PLY);
+        if game.game_status == GameStatus::InProgress && game.moves_made == BOOK_PLY {
+            entries.push((game.board_set, game.board_p1, 0));
+        }
+    }
+    let mut records: Vec<[u8; 5]> = book_bytes(&entries).chunks_exact(5).map(|record| record.try_into().unwrap()).collect();
+    records.dedup_by_key(|record| i32::from_be_bytes(record[..4].try_into().unwrap()));
+    let small_book = OpeningBook::from_bytes(&records.concat());
+    let book_options = ExportOptions {source: SampleSource::Book, min_ply: 22, max_ply: 22, ..options};
+    for sample in generate_shard(0, 5, &book_options, &mut table, &small_book, &mut nodes).unwrap() {
+        assert_eq!(sample.moves_made, 22);
+    }
+}
+
+fn random_mlp(rng: &mut Rng, sizes: &[usize]) -> Mlp {
+    let mut weight = || (rng.below(2001) as f32 - 1000.0) / 4000.0;
+    let mut inputs = SAMPLE_PLANES_LEN;
+    let mut layers = Vec::new();
+    for &outputs in sizes {
+        layers.push(((0..inputs * outputs).map(|_| weight()).collect(), (0..outputs).map(|_| weight()).collect()));
+        inputs = outputs;
+    }
+    Mlp::new(layers).unwrap()
+}
+
+#[test]
+fn heuristic_search_hosts_an_incremental_network() {
+    let mut rng = Rng::new(50);
+    let mut model = random_mlp(&mut rng, &[16, 8, 1]);
+    assert_eq!(Mlp::from_bytes(&model.to_bytes()), Ok(model.clone()));
+    let bytes = model.to_bytes();
+    assert!(Mlp::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    assert!(Mlp::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+    assert!(Mlp::new(vec![(vec![0.0; 10], vec![0.0])]).is_err());
+    assert!(Mlp::new(vec![(vec![0.0; 2 * SAMPLE_PLANES_LEN], vec![0.0; 2])]).is_err());
+
+    // The accumulator updated move by move agrees with evaluating from scratch, forwards and backwards
+    let from_scratch = |model: &Mlp, game: &Game| {
+        model.predict(&position_planes(game.board_set, game.board_set & game.board_p1, game.moves_made))
+    };
+    for _ in 0..20 {
+        let mut game = Game::new();
+        model.reset(&game);
+        let mut played = Vec::new();
+        while game.game_status == GameStatus::InProgress {
+            let col_num = rng.below(COLS as u64) as u8;
+            if let (true, row_number) = game.make_move(col_num) {
+                model.make_move(&game, col_num, row_number);
+                played.push((col_num, row_number));
+                assert!((model.evaluate(&game) - from_scratch(&model, &game)).abs() < 1e-3);
+            }
+        }
+        for &(col_num, row_number) in played.iter().rev() {
+            game.unmake_move(col_num, row_number);
+            model.unmake_move(&game, col_num, row_number);
+            assert!((model.evaluate(&game) - from_scratch(&model, &game)).abs() < 1e-3);
+        }
+    }
+
+    // Searched to the end of the game the estimates don't matter and the solver's scores come back
+    let book = OpeningBook::new();
+    let mut table = TranspositionTable::new(20);
+    let mut nodes = 0;
+    let mut checked = 0;
+    while checked < 10 {
+        let (mut game, _) = random_game(&mut rng, 32);
+        if game.game_status != GameStatus::InProgress {
+            continue;
+        }
+        let score = search(&mut game, &mut table, &book, &mut nodes) as f32;
+        let empty = (42 - game.moves_made) as u8;
+        assert_eq!(heuristic_search(&mut game, &mut model, empty, &mut nodes), score);
+        assert_eq!(heuristic_search(&mut game, &mut ThreatEvaluator, empty, &mut nodes), score);
+        let (col_num, best) = heuristic_best_move(&mut game, &mut ThreatEvaluator, empty, &mut nodes).unwrap();
+        assert_eq!(best, score);
+        assert_eq!(column_scores(&mut game, &mut table, &book, &mut nodes)[col_num as usize], Some(score as i8));
+        checked += 1;
+    }
+
+    // Shallow searches still see forced wins within their depth
+    let mut game = game_from_moves("3344").unwrap();
+    let scores = heuristic_column_scores(&mut game, &mut model, 4, &mut nodes);
+    assert_eq!(scores[2], Some(18.0));
+    assert_eq!(scores[5], Some(18.0));
+
+    let mut config = EngineConfig::new("net", 16);
+    assert!(config.apply("depth=0").is_err());
+    assert!(config.apply("model=/nonexistent/net.c4nn").is_err());
+    config.apply("depth=4").unwrap();
+    assert_eq!(config.depth, Some(4));
+}
+
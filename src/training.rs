@@ -0,0 +1,404 @@
+use crate::game::*;
+use crate::engine::*;
+use crate::book::*;
+use crate::positions::*;
+use crate::puzzle::moves_for_position;
+use crate::random::Rng;
+use std::collections::HashSet;
+
+// Solver-labelled positions for training evaluators, written as NPZ shards numpy.load reads directly. Every shard
+// holds these arrays, N being its number of samples:
+//   planes        uint8 (N, 4, 6, 7)  player one's stones, player two's stones, all ones when player one is to
+//                                     move, and the squares playable next. Row 0 is the bottom row.
+//   score         int8 (N,)           solver score for the player to move
+//   best_move     uint8 (N,)          lowest column reaching the score
+//   child_scores  int8 (N, 7)         score of every column for the player to move, -128 where it's full
+//   moves_made    uint8 (N,)
+
+const PLANES: usize = 4;
+pub const SAMPLE_PLANES_LEN: usize = PLANES * ROWS as usize * COLS as usize;
+// Stands in for a full column in child_scores
+const NO_SCORE: i8 = i8::MIN;
+// One self-play move in this many is random rather than best, so games don't all follow the same lines
+const SELF_PLAY_RANDOM_MOVES: u64 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub board_set: u64,
+    // Player one's stones, masked by board_set
+    pub p1_squares: u64,
+    pub moves_made: i8,
+    pub score: i8,
+    pub best_move: u8,
+    pub child_scores: [Option<i8>; 7],
+}
+
+impl Sample {
+    // Scores every column of a position in progress. The game is restored before returning.
+    pub fn solve(game: &mut Game, transposition_table: &mut TranspositionTable, book: &OpeningBook,
+            nodes: &mut u64) -> Self {
+        let child_scores = column_scores(game, transposition_table, book, nodes);
+        let score = child_scores.iter().flatten().copied().max().expect("a game in progress has a playable column");
+        let best_move = child_scores.iter().position(|&col_score| col_score == Some(score)).unwrap() as u8;
+        Sample {
+            board_set: game.board_set,
+            p1_squares: game.board_set & game.board_p1,
+            moves_made: game.moves_made,
+            score,
+            best_move,
+            child_scores,
+        }
+    }
+
+    pub fn planes(&self) -> [u8; SAMPLE_PLANES_LEN] {
+        position_planes(self.board_set, self.p1_squares, self.moves_made)
+    }
+}
+
+// Index into a sample's planes, which are in row-major (plane, row, column) order
+pub fn plane_index(plane: usize, row: usize, col_num: usize) -> usize {
+    (plane * ROWS as usize + row) * COLS as usize + col_num
+}
+
+// The planes of a position as written to shards, p1_squares masked by board_set
+pub fn position_planes(board_set: u64, p1_squares: u64, moves_made: i8) -> [u8; SAMPLE_PLANES_LEN] {
+    let player_one_turn = moves_made % 2 == 0;
+    let boards = [
+        p1_squares,
+        board_set & !p1_squares,
+        if player_one_turn {*BOARD_MASK} else {0},
+        get_playable_squares(board_set),
+    ];
+    let mut planes = [0; SAMPLE_PLANES_LEN];
+    for (plane, board) in boards.into_iter().enumerate() {
+        for row in 0..ROWS as usize {
+            for col_num in 0..COLS as usize {
+                planes[plane_index(plane, row, col_num)] = (board >> (8 * col_num + row) & 1) as u8;
+            }
+        }
+    }
+    planes
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleSource {
+    // Random games from the empty board
+    Random,
+    // Positions in the ply 12 book, continued with random moves
+    Book,
+    // Games where the solver plays itself from random openings, with a share of random moves
+    SelfPlay,
+}
+
+impl SampleSource {
+    pub const ALL: [SampleSource; 3] = [SampleSource::Random, SampleSource::Book, SampleSource::SelfPlay];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SampleSource::Random => "random",
+            SampleSource::Book => "book",
+            SampleSource::SelfPlay => "selfplay",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|source| source.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportOptions {
+    pub source: SampleSource,
+    // Moves played in the sampled positions. Book positions can't come before BOOK_PLY.
+    pub min_ply: i8,
+    pub max_ply: i8,
+    pub shard_size: usize,
+    pub seed: u64,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            source: SampleSource::Random,
+            min_ply: 14,
+            max_ply: 30,
+            shard_size: 100_000,
+            seed: 0,
+        }
+    }
+}
+
+// Sample counts of the shards `total` samples are split into, all full but the last
+pub fn shard_sizes(total: usize, shard_size: usize) -> Vec<usize> {
+    (0..total.div_ceil(shard_size.max(1))).map(|shard| shard_size.min(total - shard * shard_size)).collect()
+}
+
+pub fn shard_file_name(shard: usize) -> String {
+    format!("shard-{shard:05}.npz")
+}
+
+// Every shard has its own random stream, so shards can be generated separately or in parallel
+fn shard_seed(seed: u64, shard: usize) -> u64 {
+    Rng::new(seed ^ (shard as u64).wrapping_mul(0x9e3779b97f4a7c15)).next_u64()
+}
+
+// A position reached by `ply` random moves, from a random book entry for the book source. None if the game ends
+// first.
+fn start_position(source: SampleSource, ply: i8, rng: &mut Rng, book: &OpeningBook) -> Option<Game> {
+    let mut game = Game::new();
+    if source == SampleSource::Book {
+        let (code, _) = book.entry(rng.below(book.len() as u64) as usize)?;
+        let (board_set, board_p1) = decode(code);
+        for col_num in moves_for_position(board_set, board_set & board_p1)? {
+            game.make_move(col_num);
+        }
+    }
+    play_random_moves(&mut game, &mut String::new(), rng, ply).then_some(game)
+}
+
+// `count` distinct solved positions for one shard, the same for a given seed and shard whatever other shards were
+// generated. Positions may repeat across shards. Fewer come back when MAX_REJECTIONS games in a row add nothing,
+// e.g. a narrow ply range with few positions.
+pub fn generate_shard(shard: usize, count: usize, options: &ExportOptions, transposition_table: &mut TranspositionTable,
+        book: &OpeningBook, nodes: &mut u64) -> Result<Vec<Sample>, String> {
+    if !(0 <= options.min_ply && options.min_ply <= options.max_ply && options.max_ply < 42) {
+        return Err(format!("ply range {}..={} isn't within 0..42", options.min_ply, options.max_ply));
+    }
+    if options.source == SampleSource::Book && book.is_empty() {
+        return Err("the opening book has no entries".to_string());
+    }
+    let mut rng = Rng::new(shard_seed(options.seed, shard));
+    let mut seen = HashSet::new();
+    let mut samples = Vec::new();
+    let mut rejections = 0;
+    while samples.len() < count && rejections < MAX_REJECTIONS {
+        let found = samples.len();
+        let ply = match options.source {
+            // Self-play games run through the whole range
+            SampleSource::SelfPlay => options.min_ply,
+            _ => options.min_ply + rng.below((options.max_ply - options.min_ply + 1) as u64) as i8,
+        };
+        if let Some(mut game) = start_position(options.source, ply, &mut rng, book) {
+            while game.game_status == GameStatus::InProgress && game.moves_made <= options.max_ply && samples.len() < count {
+                let sample = Sample::solve(&mut game, transposition_table, book, nodes);
+                if seen.insert((sample.board_set, sample.p1_squares)) {
+                    samples.push(sample);
+                }
+                if options.source != SampleSource::SelfPlay {
+                    break;
+                }
+                if rng.below(SELF_PLAY_RANDOM_MOVES) == 0 {
+                    let ply = game.moves_made + 1;
+                    play_random_moves(&mut game, &mut String::new(), &mut rng, ply);
+                } else {
+                    let best: Vec<u8> = (0..COLS).filter(|&col_num| sample.child_scores[col_num as usize] == Some(sample.score)).collect();
+                    game.make_move(best[rng.below(best.len() as u64) as usize]);
+                }
+            }
+        }
+        rejections = if samples.len() > found {0} else {rejections + 1};
+    }
+    Ok(samples)
+}
+
+// The samples as an uncompressed NPZ archive
+pub fn to_npz(samples: &[Sample]) -> Vec<u8> {
+    let n = samples.len();
+    let child_scores: Vec<u8> = samples.iter()
+        .flat_map(|sample| sample.child_scores.map(|col_score| col_score.unwrap_or(NO_SCORE) as u8))
+        .collect();
+    let arrays = [
+        ("planes.npy", npy("|u1", &[n, PLANES, ROWS as usize, COLS as usize], &samples.iter().flat_map(|sample| sample.planes()).collect::<Vec<u8>>())),
+        ("score.npy", npy("|i1", &[n], &samples.iter().map(|sample| sample.score as u8).collect::<Vec<u8>>())),
+        ("best_move.npy", npy("|u1", &[n], &samples.iter().map(|sample| sample.best_move).collect::<Vec<u8>>())),
+        ("child_scores.npy", npy("|i1", &[n, COLS as usize], &child_scores)),
+        ("moves_made.npy", npy("|u1", &[n], &samples.iter().map(|sample| sample.moves_made as u8).collect::<Vec<u8>>())),
+    ];
+    zip_stored(&arrays)
+}
+
+// Reads an archive written by to_npz
+#[cfg(test)]
+pub fn from_npz(bytes: &[u8]) -> Result<Vec<Sample>, String> {
+    let files = unzip_stored(bytes)?;
+    let array = |name: &str, descr: &str, row_len: usize| -> Result<(usize, &[u8]), String> {
+        let (_, file) = files.iter().find(|(file_name, _)| file_name == name).ok_or(format!("no {name} in the archive"))?;
+        let (file_descr, shape, data) = parse_npy(file).map_err(|error| format!("{name}: {error}"))?;
+        let n = shape.first().copied().unwrap_or(0);
+        if file_descr != descr || shape.iter().skip(1).product::<usize>() != row_len || data.len() != n * row_len {
+            return Err(format!("{name} doesn't have the expected type and shape"));
+        }
+        Ok((n, data))
+    };
+    let (n, planes) = array("planes.npy", "|u1", SAMPLE_PLANES_LEN)?;
+    let arrays = [
+        array("score.npy", "|i1", 1)?,
+        array("best_move.npy", "|u1", 1)?,
+        array("child_scores.npy", "|i1", COLS as usize)?,
+        array("moves_made.npy", "|u1", 1)?,
+    ];
+    if arrays.iter().any(|&(rows, _)| rows != n) {
+        return Err("arrays hold different numbers of samples".to_string());
+    }
+    let [(_, score_bytes), (_, best_moves), (_, child_scores), (_, moves_made)] = arrays;
+
+    let board = |planes: &[u8], plane: usize| (0..ROWS as usize).flat_map(|row| (0..COLS as usize).map(move |col_num| (row, col_num)))
+        .filter(|&(row, col_num)| planes[plane_index(plane, row, col_num)] != 0)
+        .fold(0u64, |board, (row, col_num)| board | 1 << (8 * col_num + row));
+    (0..n).map(|i| {
+        let planes = &planes[i * SAMPLE_PLANES_LEN..(i + 1) * SAMPLE_PLANES_LEN];
+        let (p1_squares, p2_squares) = (board(planes, 0), board(planes, 1));
+        if p1_squares & p2_squares != 0 {
+            return Err(format!("sample {i} has both players on a square"));
+        }
+        let mut scores = [None; 7];
+        for (col_num, &col_score) in child_scores[i * COLS as usize..(i + 1) * COLS as usize].iter().enumerate() {
+            scores[col_num] = (col_score as i8 != NO_SCORE).then_some(col_score as i8);
+        }
+        Ok(Sample {
+            board_set: p1_squares | p2_squares,
+            p1_squares,
+            moves_made: moves_made[i] as i8,
+            score: score_bytes[i] as i8,
+            best_move: best_moves[i],
+            child_scores: scores,
+        })
+    }).collect()
+}
+
+// A numpy .npy file, format version 1.0
+fn npy(descr: &str, shape: &[usize], data: &[u8]) -> Vec<u8> {
+    let dims: Vec<String> = shape.iter().map(|dim| dim.to_string()).collect();
+    let shape = if dims.len() == 1 {format!("({},)", dims[0])} else {format!("({})", dims.join(", "))};
+    let mut header = format!("{{'descr': '{descr}', 'fortran_order': False, 'shape': {shape}, }}");
+    // Padded with spaces so the data starts 64 byte aligned, and ended by a newline
+    let unpadded = 10 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    header.push('\n');
+    let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+    bytes.extend((header.len() as u16).to_le_bytes());
+    bytes.extend(header.as_bytes());
+    bytes.extend(data);
+    bytes
+}
+
+// Type, shape and data of a .npy file written by npy
+#[cfg(test)]
+fn parse_npy(bytes: &[u8]) -> Result<(&str, Vec<usize>, &[u8]), String> {
+    if bytes.len() < 10 || &bytes[..8] != b"\x93NUMPY\x01\x00" {
+        return Err("not a version 1.0 npy file".to_string());
+    }
+    let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+    let header = bytes.get(10..10 + header_len).and_then(|header| std::str::from_utf8(header).ok())
+        .ok_or("truncated npy header")?;
+    let field = |name: &str, end: char| header.split_once(name).and_then(|(_, rest)| rest.split_once(end)).map(|(value, _)| value);
+    let descr = field("'descr': '", '\'').ok_or("no descr in the npy header")?;
+    if !header.contains("'fortran_order': False") {
+        return Err("only C order arrays are read".to_string());
+    }
+    let shape = field("'shape': (", ')').ok_or("no shape in the npy header")?
+        .split(',').map(str::trim).filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse().map_err(|_| format!("bad dimension {dim:?}")))
+        .collect::<Result<Vec<usize>, String>>()?;
+    Ok((descr, shape, &bytes[10 + header_len..]))
+}
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+
+// Version needed to extract through extra field length, shared by local and central headers. Stored, no flags,
+// dated 1980-01-01.
+fn zip_entry_fields(name: &str, data: &[u8]) -> Vec<u8> {
+    let mut fields = Vec::new();
+    for value in [20u16, 0, 0, 0, 0x21] {
+        fields.extend(value.to_le_bytes());
+    }
+    fields.extend(crc32(data).to_le_bytes());
+    fields.extend((data.len() as u32).to_le_bytes());
+    fields.extend((data.len() as u32).to_le_bytes());
+    fields.extend((name.len() as u16).to_le_bytes());
+    fields.extend(0u16.to_le_bytes());
+    fields
+}
+
+// A zip archive of uncompressed files, which is all an .npz needs. Files are limited to 4 GiB.
+fn zip_stored(files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut central = Vec::new();
+    for (name, data) in files {
+        let offset = bytes.len() as u32;
+        bytes.extend(LOCAL_HEADER.to_le_bytes());
+        bytes.extend(zip_entry_fields(name, data));
+        bytes.extend(name.as_bytes());
+        bytes.extend(data);
+
+        central.extend(CENTRAL_HEADER.to_le_bytes());
+        central.extend(20u16.to_le_bytes());
+        central.extend(zip_entry_fields(name, data));
+        // Comment length, disk number and internal and external attributes
+        central.extend([0; 10]);
+        central.extend(offset.to_le_bytes());
+        central.extend(name.as_bytes());
+    }
+    let central_offset = bytes.len() as u32;
+    bytes.extend(&central);
+    bytes.extend(END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+    bytes.extend([0; 4]);
+    bytes.extend((files.len() as u16).to_le_bytes());
+    bytes.extend((files.len() as u16).to_le_bytes());
+    bytes.extend((central.len() as u32).to_le_bytes());
+    bytes.extend(central_offset.to_le_bytes());
+    bytes.extend([0; 2]);
+    bytes
+}
+
+// Files of a zip archive of uncompressed files, checking their CRCs. The central directory isn't read.
+#[cfg(test)]
+fn unzip_stored(bytes: &[u8]) -> Result<Vec<(String, &[u8])>, String> {
+    let u16_at = |at: usize| bytes.get(at..at + 2).map(|field| u16::from_le_bytes([field[0], field[1]]) as usize)
+        .ok_or("truncated zip archive");
+    let u32_at = |at: usize| bytes.get(at..at + 4).map(|field| u32::from_le_bytes(field.try_into().unwrap()))
+        .ok_or("truncated zip archive");
+    let mut files = Vec::new();
+    let mut at = 0;
+    while u32_at(at)? == LOCAL_HEADER {
+        // Bit 3 puts the sizes after the data
+        if u16_at(at + 6)? & 8 != 0 || u16_at(at + 8)? != 0 {
+            return Err("only uncompressed zip archives are read".to_string());
+        }
+        let (crc, size) = (u32_at(at + 14)?, u32_at(at + 18)? as usize);
+        let name_start = at + 30;
+        let data_start = name_start + u16_at(at + 26)? + u16_at(at + 28)?;
+        let name = bytes.get(name_start..name_start + u16_at(at + 26)?).ok_or("truncated zip archive")?;
+        let data = bytes.get(data_start..data_start + size).ok_or("truncated zip archive")?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        if crc32(data) != crc {
+            return Err(format!("{name} fails its CRC check"));
+        }
+        files.push((name, data));
+        at = data_start + size;
+    }
+    Ok(files)
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {0xedb88320 ^ (crc >> 1)} else {crc >> 1};
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+// CRC-32 as used by zip
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}